@@ -2,7 +2,10 @@ use std::str::FromStr;
 
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 
-use rgrok::{parallel::rgrok_dir_parallel, rgrok_dir, Args, Output};
+use rgrok::{
+    filter::ItemFilter, parallel::rgrok_dir_parallel, render::RenderOptions, replace::Rewrite,
+    rgrok_dir, search::SortBy, Args, Output,
+};
 use syntect::{highlighting::ThemeSet, parsing::SyntaxSet};
 
 fn criterion_benchmark(c: &mut Criterion) {
@@ -14,9 +17,18 @@ fn criterion_benchmark(c: &mut Criterion) {
         BenchmarkId::new("single-thread", "Self"),
         &Args {
             path: ".".into(),
-            regex: regex::Regex::from_str("fn").unwrap(),
+            regex: Some(regex::Regex::from_str("fn").unwrap()),
             parallel: false,
             output: Output::Null,
+            sort: SortBy::None,
+            filter: ItemFilter::default(),
+            within: Vec::new(),
+            strict: false,
+            fallback: false,
+            no_index: false,
+            rewrite: Rewrite::default(),
+            render: RenderOptions::default(),
+            mode: None,
         },
         |b, i| b.iter(|| rgrok_dir(i.clone(), &ps, &ts)),
     );
@@ -24,9 +36,18 @@ fn criterion_benchmark(c: &mut Criterion) {
         BenchmarkId::new("parallel", "Self"),
         &Args {
             path: ".".into(),
-            regex: regex::Regex::from_str("fn").unwrap(),
+            regex: Some(regex::Regex::from_str("fn").unwrap()),
             parallel: true,
             output: Output::Null,
+            sort: SortBy::None,
+            filter: ItemFilter::default(),
+            within: Vec::new(),
+            strict: false,
+            fallback: false,
+            no_index: false,
+            rewrite: Rewrite::default(),
+            render: RenderOptions::default(),
+            mode: None,
         },
         |b, i| b.iter(|| rgrok_dir_parallel(i.clone(), &ps, &ts)),
     );
@@ -0,0 +1,88 @@
+use crate::util::tokens_to_string;
+
+use syn::{Attribute, Meta, NestedMeta};
+
+/// Whether the path of an attribute or derive is `name`, either in full or by its last segment.
+pub fn name_matches(path: &str, name: &str) -> bool {
+    path == name || path.rsplit("::").next() == Some(name)
+}
+
+/// The paths of the attributes, e.g. `tokio::test` for `#[tokio::test]`.
+pub fn attr_paths(attrs: &[Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .map(|attr| tokens_to_string(&attr.path))
+        .collect()
+}
+
+/// Whether any of the attributes is named `name`, either by its full path or its last segment,
+/// so `test` matches both `#[test]` and `#[tokio::test]`.
+pub fn has_attr(attrs: &[Attribute], name: &str) -> bool {
+    attr_paths(attrs)
+        .iter()
+        .any(|path| name_matches(path, name))
+}
+
+/// The paths of the traits in the `#[derive(..)]` attributes.
+pub fn derived(attrs: &[Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("derive"))
+        .filter_map(|attr| attr.parse_meta().ok())
+        .flat_map(|meta| match meta {
+            Meta::List(list) => list
+                .nested
+                .iter()
+                .filter_map(|nested| match nested {
+                    NestedMeta::Meta(meta) => Some(tokens_to_string(meta.path())),
+                    NestedMeta::Lit(_) => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// Whether the attributes include `#[derive(..)]` of the trait `name`.
+pub fn derives(attrs: &[Attribute], name: &str) -> bool {
+    derived(attrs).iter().any(|path| name_matches(path, name))
+}
+
+/// The predicates that must hold for a `#[cfg(..)]` on these attributes to be enabled, such as
+/// `test` or `feature=serde`. Predicates under `not(..)` are left out.
+pub fn cfg_predicates(attrs: &[Attribute]) -> Vec<String> {
+    let mut predicates = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("cfg")) {
+        if let Ok(Meta::List(list)) = attr.parse_meta() {
+            for nested in &list.nested {
+                collect_predicates(nested, &mut predicates);
+            }
+        }
+    }
+    predicates
+}
+
+fn collect_predicates(nested: &NestedMeta, predicates: &mut Vec<String>) {
+    match nested {
+        NestedMeta::Meta(Meta::Path(path)) => predicates.push(tokens_to_string(path)),
+        NestedMeta::Meta(Meta::NameValue(name_value)) => predicates.push(format!(
+            "{}={}",
+            tokens_to_string(&name_value.path),
+            normalize_predicate(&tokens_to_string(&name_value.lit))
+        )),
+        NestedMeta::Meta(Meta::List(list)) if !list.path.is_ident("not") => {
+            for nested in &list.nested {
+                collect_predicates(nested, predicates);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Strip the quotes and spaces from a predicate, so `feature = "serde"` reads `feature=serde`.
+pub fn normalize_predicate(predicate: &str) -> String {
+    predicate
+        .chars()
+        .filter(|c| *c != '"' && !c.is_whitespace())
+        .collect()
+}
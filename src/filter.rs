@@ -0,0 +1,142 @@
+use crate::attrs::normalize_predicate;
+use crate::query::Query;
+use crate::util::ItemType;
+use crate::vis::{effective_vis, Vis};
+use crate::visit::{ancestors, ItemSpan, Node};
+
+use clap::Clap;
+
+/// Restricts which items are reported for a match.
+#[derive(Clap, Clone, Default)]
+pub struct ItemFilter {
+    /// Only report items of these kinds, e.g. `--kind fn,struct`.
+    #[clap(
+        long,
+        global = true,
+        use_delimiter = true,
+        multiple_values = false,
+        multiple_occurrences = true
+    )]
+    pub kind: Vec<ItemType>,
+    /// Never report items of these kinds, e.g. `--not-kind use`.
+    #[clap(
+        long,
+        global = true,
+        use_delimiter = true,
+        multiple_values = false,
+        multiple_occurrences = true
+    )]
+    pub not_kind: Vec<ItemType>,
+    /// Only report items matching a structural query, e.g. `--query 'fn(ret: Result<_, *>)'`.
+    #[clap(long, global = true)]
+    pub query: Option<Query>,
+    /// Only report items with this attribute, e.g. `--attr test`.
+    #[clap(
+        long,
+        global = true,
+        multiple_values = false,
+        multiple_occurrences = true
+    )]
+    pub attr: Vec<String>,
+    /// Only report items enabled by this cfg predicate, on the item or an enclosing item,
+    /// e.g. `--cfg feature=serde`.
+    #[clap(
+        long,
+        global = true,
+        multiple_values = false,
+        multiple_occurrences = true
+    )]
+    pub cfg: Vec<String>,
+    /// Only report items deriving these traits, e.g. `--derive Clone`.
+    #[clap(
+        long,
+        global = true,
+        use_delimiter = true,
+        multiple_values = false,
+        multiple_occurrences = true
+    )]
+    pub derive: Vec<String>,
+    /// Never report items deriving these traits, e.g. `--not-derive Debug`.
+    #[clap(
+        long,
+        global = true,
+        use_delimiter = true,
+        multiple_values = false,
+        multiple_occurrences = true
+    )]
+    pub not_derive: Vec<String>,
+    /// Only report items with this effective visibility: pub, pub(crate) or private.
+    #[clap(
+        long,
+        global = true,
+        use_delimiter = true,
+        multiple_values = false,
+        multiple_occurrences = true
+    )]
+    pub vis: Vec<Vis>,
+}
+
+impl ItemFilter {
+    /// Whether the item at `index` passes the filter.
+    pub fn accepts<N: Node>(&self, items: &[ItemSpan<N>], index: usize) -> bool {
+        self.accepts_shape(&items[index]) && self.accepts_properties(items, index)
+    }
+
+    /// Whether the item is the kind of item asked for, by `--kind` and `--query`.
+    fn accepts_shape<N: Node>(&self, item: &ItemSpan<N>) -> bool {
+        self.accepts_kind(item.item_type)
+            && self.query.as_ref().is_none_or(|query| query.matches(item))
+    }
+
+    /// Whether the item has the attributes and visibility asked for.
+    /// Its ancestors are needed for the cfg predicates it inherits, and its effective visibility.
+    fn accepts_properties<N: Node>(&self, items: &[ItemSpan<N>], index: usize) -> bool {
+        let node = &items[index].node;
+        self.attr.iter().all(|name| node.has_attr(name))
+            && self.derive.iter().all(|name| node.derives(name))
+            && !self.not_derive.iter().any(|name| node.derives(name))
+            && (self.vis.is_empty() || self.vis.contains(&effective_vis(items, index)))
+            && (self.cfg.is_empty() || {
+                let predicates: Vec<String> = ancestors(items, index)
+                    .flat_map(|i| items[i].node.cfg_predicates())
+                    .collect();
+                self.cfg
+                    .iter()
+                    .all(|cfg| predicates.contains(&normalize_predicate(cfg)))
+            })
+    }
+
+    /// Whether the filter lets every item through.
+    pub fn is_empty(&self) -> bool {
+        self.kind.is_empty()
+            && self.not_kind.is_empty()
+            && self.query.is_none()
+            && self.attr.is_empty()
+            && self.cfg.is_empty()
+            && self.derive.is_empty()
+            && self.not_derive.is_empty()
+            && self.vis.is_empty()
+    }
+
+    pub fn accepts_kind(&self, item_type: ItemType) -> bool {
+        (self.kind.is_empty() || self.kind.contains(&item_type))
+            && !self.not_kind.contains(&item_type)
+    }
+
+    /// Starting from the innermost item at `index`, find the closest item of the kind asked for.
+    /// That item must also have the properties asked for, a private method doesn't make its
+    /// public impl match instead.
+    pub fn select<N: Node>(
+        &self,
+        items: &[ItemSpan<N>],
+        mut index: Option<usize>,
+    ) -> Option<usize> {
+        while let Some(i) = index {
+            if self.accepts_shape(&items[i]) {
+                return Some(i).filter(|&i| self.accepts_properties(items, i));
+            }
+            index = items[i].parent;
+        }
+        None
+    }
+}
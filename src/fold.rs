@@ -0,0 +1,235 @@
+//! Folding away the parts of an item far from its matches, so long items stay readable in a terminal.
+//!
+//! Folds are made of whole statements, match arms, fields, variants or members, taken from the
+//! `syn` AST, so what is left still reads like Rust.
+
+use crate::render::wrapper;
+use crate::util::ItemType;
+
+use std::ops::Range;
+
+use proc_macro2::LineColumn;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+
+/// Lists of sibling nodes that can be folded together, as the start and end of each node.
+#[derive(Default)]
+struct Units {
+    lists: Vec<Vec<(LineColumn, LineColumn)>>,
+}
+
+impl Units {
+    fn push<T: Spanned>(&mut self, siblings: impl IntoIterator<Item = T>) {
+        self.lists.push(
+            siblings
+                .into_iter()
+                .map(|node| {
+                    let span = node.span();
+                    (span.start(), span.end())
+                })
+                .collect(),
+        );
+    }
+}
+
+impl<'ast> Visit<'ast> for Units {
+    fn visit_block(&mut self, block: &'ast syn::Block) {
+        self.push(&block.stmts);
+        visit::visit_block(self, block);
+    }
+
+    fn visit_expr_match(&mut self, expr: &'ast syn::ExprMatch) {
+        self.push(&expr.arms);
+        visit::visit_expr_match(self, expr);
+    }
+
+    fn visit_expr_struct(&mut self, expr: &'ast syn::ExprStruct) {
+        self.push(&expr.fields);
+        visit::visit_expr_struct(self, expr);
+    }
+
+    fn visit_fields_named(&mut self, fields: &'ast syn::FieldsNamed) {
+        self.push(&fields.named);
+        visit::visit_fields_named(self, fields);
+    }
+
+    fn visit_item_enum(&mut self, item: &'ast syn::ItemEnum) {
+        self.push(&item.variants);
+        visit::visit_item_enum(self, item);
+    }
+
+    fn visit_item_impl(&mut self, item: &'ast syn::ItemImpl) {
+        self.push(&item.items);
+        visit::visit_item_impl(self, item);
+    }
+
+    fn visit_item_trait(&mut self, item: &'ast syn::ItemTrait) {
+        self.push(&item.items);
+        visit::visit_item_trait(self, item);
+    }
+
+    fn visit_item_foreign_mod(&mut self, item: &'ast syn::ItemForeignMod) {
+        self.push(&item.items);
+        visit::visit_item_foreign_mod(self, item);
+    }
+
+    fn visit_item_mod(&mut self, item: &'ast syn::ItemMod) {
+        if let Some((_, items)) = &item.content {
+            self.push(items);
+        }
+        visit::visit_item_mod(self, item);
+    }
+}
+
+/// The runs of lines of `text` to fold, as 0-indexed line ranges, keeping `context` lines around each line in `match_lines`.
+/// `item` is the byte range of the item within `text`, which starts at the beginning of a line.
+/// Returns no folds if the item doesn't parse on its own.
+pub fn folds(
+    item_type: ItemType,
+    text: &str,
+    item: Range<usize>,
+    match_lines: &[usize],
+    context: usize,
+) -> Vec<Range<usize>> {
+    let line_count = text.split_inclusive('\n').count();
+    let (open, close) = wrapper(item_type);
+    let parsed = format!("{}{}\n{}", open, &text[item.clone()], close);
+    let file = match syn::parse_file(&parsed) {
+        Ok(file) => file,
+        Err(_) => return Vec::new(),
+    };
+    let mut units = Units::default();
+    units.visit_file(&file);
+
+    let parsed_lines: Vec<&str> = parsed.lines().collect();
+    // Line of `text` for a 1-indexed line of `parsed`.
+    let offset = text[..item.start].matches('\n').count();
+    let skipped = open.matches('\n').count() + 1;
+    let line_of = |l: usize| (l >= skipped).then(|| l - skipped + offset);
+    // A node can only be folded if it has its lines to itself.
+    let alone = |start: LineColumn, end: LineColumn| {
+        let first = parsed_lines[start.line - 1];
+        let last = parsed_lines[end.line - 1];
+        let rest = last.chars().skip(end.column).collect::<String>();
+        let rest = rest.trim();
+        first.chars().take(start.column).all(char::is_whitespace)
+            && (rest.is_empty() || rest == "," || rest.starts_with("//"))
+    };
+
+    let mut kept = vec![false; line_count];
+    for &line in match_lines {
+        let end = (line + context).min(line_count.saturating_sub(1));
+        for keep in &mut kept[line.saturating_sub(context)..=end] {
+            *keep = true;
+        }
+    }
+    let any_kept = |lines: &(usize, usize)| kept[lines.0..=lines.1].iter().any(|&k| k);
+
+    let mut hidden = vec![false; line_count];
+    let mut hide = |lines: (usize, usize)| {
+        for hide in &mut hidden[lines.0..=lines.1] {
+            *hide = true;
+        }
+    };
+    for list in units.lists {
+        // Consecutive siblings fold together, along with any comments between them.
+        let mut run: Option<(usize, usize)> = None;
+        for (start, end) in list {
+            let lines = match (line_of(start.line), line_of(end.line)) {
+                (Some(first), Some(last)) if last < line_count && alone(start, end) => {
+                    Some((first, last))
+                }
+                _ => None,
+            };
+            match (lines, run) {
+                (Some(lines), Some((first, _))) if !any_kept(&(first, lines.1)) => {
+                    run = Some((first, lines.1))
+                }
+                _ => {
+                    if let Some(run) = run.take() {
+                        hide(run);
+                    }
+                    run = lines.filter(|lines| !any_kept(lines));
+                }
+            }
+        }
+        if let Some(run) = run {
+            hide(run);
+        }
+    }
+
+    // A single line is as short as the marker replacing it.
+    let mut folds: Vec<Range<usize>> = Vec::new();
+    for (i, &hide) in hidden.iter().enumerate() {
+        match folds.last_mut() {
+            Some(fold) if hide && fold.end == i => fold.end = i + 1,
+            _ if hide => folds.push(i..i + 1),
+            _ => {}
+        }
+    }
+    folds.retain(|fold| fold.len() > 1);
+    folds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FUNCTION: &str = "\
+fn long() {
+    let a = 1;
+    let b = 2;
+    let c = 3;
+    let needle = a + b + c;
+    let d = 4;
+    let e = 5;
+    let f = 6;
+}
+";
+
+    #[test]
+    fn folds_statements_away_from_matches() {
+        let folds = folds(ItemType::Fn, FUNCTION, 0..FUNCTION.len(), &[4], 0);
+        assert_eq!(folds, [1..4, 5..8]);
+    }
+
+    #[test]
+    fn keeps_context_around_matches() {
+        let folds = folds(ItemType::Fn, FUNCTION, 0..FUNCTION.len(), &[4], 1);
+        // A single line left on either side isn't worth folding.
+        assert_eq!(folds, [1..3, 6..8]);
+        assert!(super::folds(ItemType::Fn, FUNCTION, 0..FUNCTION.len(), &[4], 2).is_empty());
+    }
+
+    #[test]
+    fn lines_are_relative_to_the_text() {
+        let text = format!("// leading comment\n{}", FUNCTION);
+        let item = text.find("fn").unwrap()..text.len();
+        assert_eq!(folds(ItemType::Fn, &text, item, &[5], 0), [2..5, 6..9]);
+    }
+
+    #[test]
+    fn methods_parse_inside_an_impl() {
+        let method = FUNCTION.replace("fn long()", "pub fn long(&self)");
+        let folds = folds(ItemType::ImplMethod, &method, 0..method.len(), &[1], 0);
+        assert_eq!(folds, vec![2..8]);
+    }
+
+    #[test]
+    fn only_folds_nodes_with_lines_of_their_own() {
+        let text = "\
+fn packed() {
+    let a = 1; let b = 2;
+    let c = 3; let d = 4;
+    let needle = 0;
+}
+";
+        assert!(folds(ItemType::Fn, text, 0..text.len(), &[3], 0).is_empty());
+    }
+
+    #[test]
+    fn no_folds_without_a_parse() {
+        let text = "fn broken( {\n    let a = 1;\n    let b = 2;\n}\n";
+        assert!(folds(ItemType::Fn, text, 0..text.len(), &[], 0).is_empty());
+    }
+}
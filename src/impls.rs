@@ -0,0 +1,30 @@
+use crate::query::Query;
+
+use std::path::PathBuf;
+
+use clap::Clap;
+use color_eyre::{eyre, Result};
+
+/// List the impls of a trait, or the impls for a type.
+#[derive(Clap, Clone)]
+pub struct Impls {
+    /// Directory or file to search, instead of --path.
+    pub path: Option<PathBuf>,
+    /// Only list impls of this trait, e.g. `--trait Display`.
+    #[clap(long = "trait")]
+    pub trait_: Option<String>,
+    /// Only list impls for this type, inherent or not, e.g. `--for Wrapper`.
+    #[clap(long = "for")]
+    pub for_: Option<String>,
+}
+
+impl Impls {
+    pub fn query(&self) -> Result<Query> {
+        if self.trait_.is_none() && self.for_.is_none() {
+            return Err(eyre::eyre!(
+                "Expected a trait with --trait or a type with --for"
+            ));
+        }
+        Ok(Query::impls(self.trait_.as_deref(), self.for_.as_deref()))
+    }
+}
@@ -0,0 +1,358 @@
+//! An on-disk index of the items of every file, so searches can skip parsing unchanged files.
+//!
+//! The index of a directory lives in its `.rgrok/index`, which searches of that directory pick up
+//! by themselves. Files are looked up by their path within the directory, and only used if their
+//! contents still have the length and hash that were indexed, so a stale index is slower but never wrong.
+
+use crate::attrs::{attr_paths, cfg_predicates, derived, name_matches};
+use crate::is_rust_file;
+use crate::query::Shape;
+use crate::util::{parse_file, ParsedFile};
+use crate::vis::Vis;
+use crate::visit::{collect_items, ItemNode, ItemSpan, Node};
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use clap::Clap;
+use color_eyre::{eyre, Result};
+use ignore::{DirEntry, Walk};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Build the index of the items in the tree, for later searches to skip parsing unchanged files.
+#[derive(Clap, Clone)]
+pub struct Index {
+    /// Directory to index, instead of --path.
+    pub path: Option<PathBuf>,
+    /// Only parse the files that changed since the index was last built, instead of starting over.
+    #[clap(long)]
+    pub update: bool,
+}
+
+impl Index {
+    pub fn run(&self, root: &Path) -> Result<()> {
+        if !root.is_dir() {
+            return Err(eyre::eyre!("Only directories can be indexed"));
+        }
+        let previous = if self.update {
+            ItemIndex::load(root)
+        } else {
+            None
+        };
+        let (index, stats) = ItemIndex::build(root, previous);
+        index.save()?;
+        println!(
+            "Indexed {} file(s) into {}, {} parsed and {} unchanged, {} failed to parse",
+            index.files.len(),
+            ItemIndex::path(root).display(),
+            stats.parsed,
+            stats.unchanged,
+            stats.failed
+        );
+        Ok(())
+    }
+}
+
+/// What the index keeps of an item in place of its syntax tree.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Facts {
+    /// Paths of the attributes.
+    attrs: Vec<String>,
+    /// Paths of the derived traits.
+    derives: Vec<String>,
+    cfg: Vec<String>,
+    vis: Option<Vis>,
+    shape: Shape,
+    signature: String,
+    body_start: Option<(usize, usize)>,
+}
+
+impl Facts {
+    fn new(items: &[ItemSpan<ItemNode>], index: usize) -> Self {
+        let node = &items[index].node;
+        Facts {
+            attrs: attr_paths(node.attrs()),
+            derives: derived(node.attrs()),
+            cfg: cfg_predicates(node.attrs()),
+            vis: ItemNode::declared_vis(items, index),
+            shape: Shape::of(node),
+            signature: node.signature(),
+            body_start: node.body_start(),
+        }
+    }
+}
+
+impl Node for Facts {
+    fn has_attr(&self, name: &str) -> bool {
+        self.attrs.iter().any(|path| name_matches(path, name))
+    }
+
+    fn derives(&self, name: &str) -> bool {
+        self.derives.iter().any(|path| name_matches(path, name))
+    }
+
+    fn cfg_predicates(&self) -> Vec<String> {
+        self.cfg.clone()
+    }
+
+    fn declared_vis(items: &[ItemSpan<Self>], index: usize) -> Option<Vis> {
+        items[index].node.vis
+    }
+
+    fn shape(&self) -> Cow<'_, Shape> {
+        Cow::Borrowed(&self.shape)
+    }
+
+    fn signature(&self) -> String {
+        self.signature.clone()
+    }
+
+    fn body_start(&self) -> Option<(usize, usize)> {
+        self.body_start
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct IndexedFile {
+    /// Lets `--update` skip reading files that haven't been touched.
+    modified: SystemTime,
+    /// Length and hash of the contents, which decide whether the items can still be used.
+    len: u64,
+    hash: u64,
+    items: Vec<ItemSpan<Facts>>,
+}
+
+impl IndexedFile {
+    /// Whether `contents` are still what was indexed.
+    fn unchanged(&self, contents: &str) -> bool {
+        self.len == contents.len() as u64 && self.hash == hash(contents)
+    }
+}
+
+/// Bumped whenever what the index records changes, so old indexes are rebuilt rather than misread.
+const VERSION: u32 = 3;
+
+/// The items of every file under a directory.
+#[derive(Serialize, Deserialize)]
+pub struct ItemIndex {
+    version: u32,
+    /// Keyed by the path of each file within the root.
+    files: BTreeMap<PathBuf, IndexedFile>,
+    #[serde(skip)]
+    root: PathBuf,
+}
+
+/// How the files were brought into the index.
+#[derive(Debug, Default)]
+struct Stats {
+    parsed: usize,
+    unchanged: usize,
+    failed: usize,
+}
+
+impl ItemIndex {
+    /// Where the index of the tree at `root` is kept.
+    pub fn path(root: &Path) -> PathBuf {
+        root.join(".rgrok").join("index")
+    }
+
+    /// Load the index of the tree at `root`, if it has one.
+    /// An index that can't be read is ignored with a warning, searches just parse every file.
+    pub fn load(root: &Path) -> Option<Self> {
+        let path = Self::path(root);
+        let file = File::open(&path).ok()?;
+        match serde_json::from_reader::<_, ItemIndex>(BufReader::new(file)) {
+            Ok(index) if index.version == VERSION => Some(ItemIndex {
+                root: root.to_path_buf(),
+                ..index
+            }),
+            Ok(_) => {
+                eprintln!(
+                    "{} was built by another version of rgrok, run `rgrok index` to rebuild it",
+                    path.display()
+                );
+                None
+            }
+            Err(e) => {
+                eprintln!("Ignoring {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// The items of the file, if it hasn't changed since it was indexed.
+    pub fn items(&self, file: &ParsedFile) -> Option<&[ItemSpan<Facts>]> {
+        let key = file.dir_entry.path().strip_prefix(&self.root).ok()?;
+        let indexed = self.files.get(key)?;
+        indexed
+            .unchanged(&file.contents)
+            .then(|| &indexed.items[..])
+    }
+
+    /// Index every rust file under `root`, keeping the entries of `previous` for unchanged files.
+    /// Files that can't be read or parsed are left out, for searches to report.
+    fn build(root: &Path, previous: Option<ItemIndex>) -> (Self, Stats) {
+        let previous = previous.map(|index| index.files).unwrap_or_default();
+        let entries: Vec<DirEntry> = Walk::new(root)
+            .filter_map(|entry| entry.ok())
+            .filter(is_rust_file)
+            .collect();
+        let indexed: Vec<(PathBuf, Option<IndexedFile>, bool)> = entries
+            .into_par_iter()
+            .filter_map(|entry| index_file(root, entry, &previous))
+            .collect();
+
+        let mut stats = Stats::default();
+        let mut files = BTreeMap::new();
+        for (key, file, parsed) in indexed {
+            match (file, parsed) {
+                (Some(file), parsed) => {
+                    if parsed {
+                        stats.parsed += 1;
+                    } else {
+                        stats.unchanged += 1;
+                    }
+                    files.insert(key, file);
+                }
+                (None, _) => stats.failed += 1,
+            }
+        }
+        let index = ItemIndex {
+            version: VERSION,
+            files,
+            root: root.to_path_buf(),
+        };
+        (index, stats)
+    }
+
+    /// Write the index out, replacing the previous one at once so searches never see half of it.
+    fn save(&self) -> Result<()> {
+        let path = Self::path(&self.root);
+        let dir = path.parent().unwrap_or(&self.root);
+        std::fs::create_dir_all(dir)?;
+        // The index only makes sense on this machine.
+        std::fs::write(dir.join(".gitignore"), "*\n")?;
+        let partial = path.with_extension("partial");
+        let mut output = BufWriter::new(File::create(&partial)?);
+        serde_json::to_writer(&mut output, self)?;
+        output.flush()?;
+        std::fs::rename(&partial, &path)?;
+        Ok(())
+    }
+}
+
+/// Index a single file, reusing its previous entry if it hasn't changed.
+/// Returns its key, its entry unless it failed to parse, and whether it had to be parsed.
+fn index_file(
+    root: &Path,
+    entry: DirEntry,
+    previous: &BTreeMap<PathBuf, IndexedFile>,
+) -> Option<(PathBuf, Option<IndexedFile>, bool)> {
+    let key = entry.path().strip_prefix(root).ok()?.to_path_buf();
+    let modified = entry.metadata().ok()?.modified().ok()?;
+    let old = previous.get(&key);
+    if let Some(old) = old.filter(|old| old.modified == modified) {
+        return Some((key, Some(old.clone()), false));
+    }
+    let file = parse_file(entry).ok()?;
+    if let Some(old) = old.filter(|old| old.unchanged(&file.contents)) {
+        let file = IndexedFile {
+            modified,
+            ..old.clone()
+        };
+        return Some((key, Some(file), false));
+    }
+    let syn_file = match syn::parse_file(&file.contents) {
+        Ok(syn_file) => syn_file,
+        Err(_) => return Some((key, None, true)),
+    };
+    let items = collect_items(&syn_file.items);
+    let items = (0..items.len())
+        .map(|i| items[i].with_node(Facts::new(&items, i)))
+        .collect();
+    Some((
+        key,
+        Some(IndexedFile {
+            modified,
+            len: file.contents.len() as u64,
+            hash: hash(&file.contents),
+            items,
+        }),
+        true,
+    ))
+}
+
+/// 64-bit FNV-1a of the contents, which unlike `DefaultHasher` is specified and won't change
+/// between builds of rgrok, so an index stays valid across them.
+fn hash(contents: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    contents.bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+    use crate::util::TempDir;
+
+    #[test]
+    fn hash_is_fnv_1a() {
+        assert_eq!(hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash("foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = TempDir::new("index");
+        let root = &dir.0;
+        dir.write(
+            "lib.rs",
+            "#[derive(Debug)]\npub struct Shared { inner: Vec<u8> }\n\
+             impl Shared { pub fn len(&self) -> usize { 0 } }\n",
+        );
+        dir.write("broken.rs", "fn broken( {}\n");
+
+        let (index, stats) = ItemIndex::build(root, None);
+        assert_eq!((stats.parsed, stats.unchanged, stats.failed), (1, 0, 1));
+        index.save().unwrap();
+        let index = ItemIndex::load(root).unwrap();
+
+        let file = dir.parsed("lib.rs");
+        let items = index.items(&file).unwrap();
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, ["struct Shared", "impl Shared", "fn len"]);
+        assert!(items[0].node.derives("Debug"));
+        assert_eq!(Facts::declared_vis(items, 0), Some(Vis::Pub));
+        let query: Query = "fn(ret: usize, self: &self)".parse().unwrap();
+        assert!(query.matches(&items[2]));
+        assert!(index.items(&dir.parsed("broken.rs")).is_none());
+
+        // Same length, different contents.
+        let mut changed = file;
+        changed.contents = changed.contents.replace("Vec<u8>", "Vec<i8>");
+        assert!(index.items(&changed).is_none());
+
+        let (_, stats) = ItemIndex::build(root, Some(index));
+        assert_eq!((stats.parsed, stats.unchanged, stats.failed), (0, 1, 1));
+    }
+
+    #[test]
+    fn other_versions_are_ignored() {
+        let dir = TempDir::new("index-version");
+        let root = &dir.0;
+        dir.write("lib.rs", "fn f() {}\n");
+        let (mut index, _) = ItemIndex::build(root, None);
+        index.version = VERSION + 1;
+        index.save().unwrap();
+        assert!(ItemIndex::load(root).is_none());
+    }
+}
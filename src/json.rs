@@ -0,0 +1,42 @@
+use crate::matches::ItemMatch;
+use crate::Compositor;
+
+use std::io::Write;
+
+/// Writes each matched item as a single line of JSON.
+pub struct JsonPrinter<W: Write> {
+    output: W,
+}
+
+impl<W: Write> JsonPrinter<W> {
+    pub fn new(output: W) -> Self {
+        Self { output }
+    }
+}
+
+impl<W: Write> Write for JsonPrinter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<W: Write> Compositor for JsonPrinter<W> {
+    type Context = ItemMatch;
+
+    fn renders(&self) -> bool {
+        false
+    }
+
+    fn write_with(
+        &mut self,
+        _: std::fmt::Arguments,
+        item: Self::Context,
+    ) -> std::result::Result<(), std::io::Error> {
+        serde_json::to_writer(&mut self.output, &item)?;
+        writeln!(self.output)
+    }
+}
@@ -1,15 +1,40 @@
+pub mod attrs;
+pub mod filter;
+pub mod fold;
+pub mod impls;
+pub mod index;
+pub mod json;
+pub mod matches;
+pub mod outline;
 pub mod parallel;
+pub mod query;
+pub mod render;
+pub mod replace;
+pub mod search;
+pub mod signature;
+pub mod tags;
+pub mod tokens;
 pub mod util;
+pub mod vis;
+pub mod visit;
 
-use crate::util::item_type;
-use crate::util::ItemType;
+use crate::filter::ItemFilter;
+use crate::impls::Impls;
+use crate::index::{Index, ItemIndex};
+use crate::json::JsonPrinter;
+use crate::matches::ItemMatch;
+use crate::outline::{Outline, OutlinePrinter};
+use crate::render::{match_markers, Palette, RenderOptions};
+use crate::replace::{ReplacePrinter, Rewrite};
+use crate::search::{line_regex, ParseError, Searcher, SortBy};
+use crate::tags::{Tags, TagsPrinter};
+use crate::tokens::TokenClass;
 
-use std::io::BufWriter;
-use syn::spanned::Spanned;
+use std::ops::Range;
 
 use std::{
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     str::FromStr,
 };
@@ -19,16 +44,15 @@ use color_eyre::{
     Result,
 };
 
-use ignore::{DirEntry, Walk};
-use regex::Regex;
+use ignore::DirEntry;
 
 use syntect::{
-    highlighting::{Color, FontStyle, Style, ThemeSet},
+    highlighting::{Style, StyleModifier, Theme, ThemeSet},
     parsing::{SyntaxReference, SyntaxSet},
-    util::{as_24_bit_terminal_escaped, LinesWithEndings},
+    util::{modify_range, LinesWithEndings},
 };
 
-use util::parse_file;
+use util::{parse_file, print_header_info, ItemType};
 
 use util::ParsedFile;
 
@@ -36,13 +60,181 @@ use clap::Clap;
 use color_eyre::Report;
 #[derive(Clap, Clone)]
 pub struct Args {
-    pub regex: regex::Regex,
+    /// Optional if an item filter such as --query or --kind is given, in which case every such item is reported.
+    #[clap(parse(try_from_str = line_regex))]
+    pub regex: Option<regex::Regex>,
     #[clap(short, long, default_value = ".")]
     pub path: PathBuf,
-    #[clap(long)]
+    #[clap(long, global = true)]
     pub parallel: bool,
-    #[clap(long, default_value = "stdout")]
+    #[clap(long, default_value = "stdout", global = true)]
     pub output: Output,
+    /// Order of the files in the output: none, path or modified. Sorting waits for the whole tree to be walked.
+    #[clap(long, default_value = "none", global = true)]
+    pub sort: SortBy,
+    #[clap(flatten)]
+    pub filter: ItemFilter,
+    /// Only match within these classes of tokens: ident, comment, doc, string or code.
+    #[clap(
+        long = "in",
+        use_delimiter = true,
+        multiple_values = false,
+        multiple_occurrences = true
+    )]
+    pub within: Vec<TokenClass>,
+    /// Exit with an error if any file fails to parse.
+    #[clap(long)]
+    pub strict: bool,
+    /// Search files that fail to parse line by line, reporting matches as items of kind `line`.
+    #[clap(long)]
+    pub fallback: bool,
+    /// Parse every file, even if the directory has an index built by `rgrok index`.
+    #[clap(long, global = true)]
+    pub no_index: bool,
+    #[clap(flatten)]
+    pub rewrite: Rewrite,
+    #[clap(flatten)]
+    pub render: RenderOptions,
+    #[clap(subcommand)]
+    pub mode: Option<Mode>,
+}
+
+#[derive(Clap, Clone)]
+pub enum Mode {
+    Outline(Outline),
+    Tags(Tags),
+    Impls(Impls),
+    Index(Index),
+}
+
+impl Mode {
+    /// The directory or file given to the subcommand, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Mode::Outline(outline) => outline.path.as_deref(),
+            Mode::Tags(tags) => tags.path.as_deref(),
+            Mode::Impls(impls) => impls.path.as_deref(),
+            Mode::Index(index) => index.path.as_deref(),
+        }
+    }
+}
+
+impl Args {
+    pub fn searcher(&self) -> Result<Searcher> {
+        if self.mode.is_none() && self.regex.is_none() && self.filter.is_empty() {
+            return Err(eyre::eyre!(
+                "Nothing to search for, expected a regex or an item filter such as --query or --kind"
+            ));
+        }
+        let mut filter = self.filter.clone();
+        if let Some(Mode::Impls(impls)) = &self.mode {
+            if filter.query.is_some() {
+                return Err(eyre::eyre!("impls can't be combined with --query"));
+            }
+            filter.query = Some(impls.query()?);
+        }
+        Ok(Searcher::new()
+            .regex(self.pattern().cloned())
+            .path(self.root())
+            .filter(filter)
+            .within(self.within.clone())
+            .fallback(self.fallback)
+            .sort(self.sort)
+            .index(if self.no_index {
+                None
+            } else {
+                ItemIndex::load(self.root())
+            }))
+    }
+
+    /// The directory or file to search, which subcommands may override.
+    pub fn root(&self) -> &Path {
+        self.mode
+            .as_ref()
+            .and_then(Mode::path)
+            .unwrap_or(&self.path)
+    }
+
+    /// The regex to search for. Subcommands list every item, so they don't use one.
+    pub fn pattern(&self) -> Option<&regex::Regex> {
+        match &self.mode {
+            Some(_) => None,
+            None => self.regex.as_ref(),
+        }
+    }
+
+    /// The compositor to write items with, for the chosen output and subcommand.
+    pub fn printer(&self) -> Result<Box<dyn Compositor<Context = ItemMatch>>> {
+        if self.rewrite.replace.is_some() {
+            if let Output::Json = self.output {
+                return Err(eyre::eyre!(
+                    "--replace can't be combined with --output json"
+                ));
+            }
+            if self.mode.is_some() {
+                return Err(eyre::eyre!("--replace can't be combined with a subcommand"));
+            }
+        }
+        Ok(match (&self.output, &self.mode) {
+            (_, Some(Mode::Tags(tags))) => Box::new(TagsPrinter::new(tags)),
+            (Output::Json, _) => Box::new(JsonPrinter::new(std::io::stdout())),
+            (_, Some(Mode::Outline(outline))) => {
+                Box::new(OutlinePrinter::new(self.output.clone(), outline.flat))
+            }
+            (_, _) if self.rewrite.replace.is_some() => {
+                let regex = self
+                    .regex
+                    .clone()
+                    .ok_or_else(|| eyre::eyre!("--replace needs a regex to replace"))?;
+                let template = self.rewrite.replace.clone().unwrap_or_default();
+                Box::new(ReplacePrinter::new(
+                    self.output.clone(),
+                    regex,
+                    template,
+                    &self.rewrite,
+                    self.render.palette() != Palette::Plain,
+                ))
+            }
+            _ => Box::new(TerminalPrinter::new(
+                self.output.clone(),
+                self.render.column,
+            )),
+        })
+    }
+
+    /// Turn the problems found during a search into an error.
+    /// Parse failures only count in strict mode.
+    pub fn check(&self, diagnostics: &Diagnostics) -> Result<()> {
+        if diagnostics.errors > 0 {
+            Err(eyre::eyre!("{} error(s) occurred", diagnostics.errors))
+        } else if self.strict && diagnostics.parse_failures > 0 {
+            Err(eyre::eyre!(
+                "{} file(s) failed to parse",
+                diagnostics.parse_failures
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Tally of the problems reported while searching, so the run can carry on past them.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    pub errors: usize,
+    pub parse_failures: usize,
+}
+
+impl Diagnostics {
+    /// Print an error from the search to stderr.
+    pub fn report(&mut self, error: &Report) {
+        if error.downcast_ref::<ParseError>().is_some() {
+            self.parse_failures += 1;
+        } else {
+            self.errors += 1;
+        }
+        eprintln!("{:#}", error);
+    }
 }
 
 impl FromStr for Output {
@@ -51,6 +243,7 @@ impl FromStr for Output {
         match s {
             "stdout" => Ok(Output::Stdout),
             "null" => Ok(Output::Null),
+            "json" => Ok(Output::Json),
             _ => Err(eyre::eyre!("Invalid output")),
         }
     }
@@ -60,21 +253,23 @@ impl FromStr for Output {
 pub enum Output {
     Stdout,
     Null,
+    /// JSON Lines on stdout, one object per matched item.
+    Json,
 }
 impl Compositor for Output {
-    type Context = ((usize, usize), ItemType);
+    type Context = ItemMatch;
 }
 impl std::io::Write for Output {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         match self {
-            Self::Stdout => std::io::stdout().write(buf),
+            Self::Stdout | Self::Json => std::io::stdout().write(buf),
             Self::Null => Ok(buf.len()),
         }
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
         match self {
-            Self::Stdout => std::io::stdout().flush(),
+            Self::Stdout | Self::Json => std::io::stdout().flush(),
             Self::Null => Ok(()),
         }
     }
@@ -84,8 +279,10 @@ impl std::io::Write for Output {
 /// Unfortunately, rustfmt is not designed to be used as a library, so we have to spawn a process to do the formatting for us.
 pub fn rustfmt(string: String) -> Result<String> {
     let mut process = Command::new("rustfmt")
+        .args(["--edition", "2018"])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .spawn()?;
     {
         let stdin = process
@@ -94,26 +291,34 @@ pub fn rustfmt(string: String) -> Result<String> {
             .ok_or_else(|| eyre::eyre!("Unable to obtain handle to stdin process."))?;
         stdin.write_all(string.as_bytes())?;
     }
-    let output = process.wait_with_output()?.stdout;
-    Ok(String::from_utf8_lossy(&output).into())
+    let output = process.wait_with_output()?;
+    if !output.status.success() {
+        return Err(eyre::eyre!(
+            "rustfmt failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into())
 }
 
 use crossterm::terminal;
 
 struct TerminalPrinter {
-    size: (u16, u16),
     output: Output,
     line: String,
+    /// List the location of each match in the header.
+    column: bool,
 }
 
 impl TerminalPrinter {
-    pub fn new(output: Output) -> Result<Self, std::io::Error> {
-        let (x, y) = terminal::size()?;
-        Ok(Self {
-            size: (x, y),
+    pub fn new(output: Output, column: bool) -> Self {
+        // Without a terminal to measure, e.g. when piped with no TERM set, use a common width.
+        let (x, _) = terminal::size().unwrap_or((80, 0));
+        Self {
             output,
             line: "-".repeat(x as _),
-        })
+            column,
+        }
     }
 }
 
@@ -121,7 +326,7 @@ impl Write for TerminalPrinter {
     /// Composites a simple line frame around the buffer.
     fn write(&mut self, buf: &[u8]) -> std::result::Result<usize, std::io::Error> {
         match &self.output {
-            Output::Stdout => {
+            Output::Stdout | Output::Json => {
                 let mut stdout = std::io::stdout();
                 stdout.write(buf)
             }
@@ -134,14 +339,25 @@ impl Write for TerminalPrinter {
 }
 
 impl Compositor for TerminalPrinter {
-    type Context = ((usize, usize), ItemType);
+    type Context = ItemMatch;
     fn write_with(
         &mut self,
         args: std::fmt::Arguments,
-        ((start, end), item_type): Self::Context,
+        item: Self::Context,
     ) -> std::result::Result<(), std::io::Error> {
         writeln!(self.output, "{}", self.line)?;
-        writeln!(self.output, "{:?}, ({}, {})", item_type, start, end)?;
+        print_header_info(&mut self.output, &item)?;
+        if self.column {
+            for m in &item.matches {
+                writeln!(
+                    self.output,
+                    "{}:{}:{}",
+                    item.path.display(),
+                    m.line,
+                    m.column
+                )?;
+            }
+        }
         writeln!(self.output, "{}", self.line)?;
         let result = self.write_fmt(args);
         writeln!(self.output, "{}", self.line)?;
@@ -154,6 +370,11 @@ impl Compositor for TerminalPrinter {
 /// A compositor is just a fancy writer that can understand some more contextual information.
 pub trait Compositor: Write {
     type Context;
+    /// Whether `write_with` makes use of the highlighted item it is given.
+    /// Compositors that only need the structured context can skip the cost of highlighting.
+    fn renders(&self) -> bool {
+        true
+    }
     fn write_with(
         &mut self,
         args: std::fmt::Arguments,
@@ -161,168 +382,260 @@ pub trait Compositor: Write {
     ) -> std::result::Result<(), std::io::Error> {
         self.write_fmt(args)
     }
+    /// Called once every item has been written, for compositors that write everything at the end.
+    fn finish(&mut self) -> std::result::Result<(), std::io::Error> {
+        Ok(())
+    }
 }
 
 pub fn rgrok_dir(args: Args, ps: &SyntaxSet, ts: &ThemeSet) -> Result<()> {
-    let mut printer = TerminalPrinter::new(args.output)?;
-    for file in Walk::new(args.path) {
-        match file {
-            Ok(dir_entry) if is_rust_file(&dir_entry) => {
-                let syntax = ps.find_syntax_for_file(dir_entry.path())?.ok_or_else(|| {
-                    eyre::eyre!(
-                        "Syntax highlight support was not found for the following file: {:?}",
-                        dir_entry.path()
-                    )
-                })?;
-                let file = parse_file(dir_entry)?;
-                grep_items(&mut printer, &file, &args.regex, syntax, ps, ts);
+    let mut printer = args.printer()?;
+    let mut diagnostics = Diagnostics::default();
+    print_items(
+        &mut *printer,
+        args.searcher()?.search(),
+        &mut diagnostics,
+        &args.render,
+        ps,
+        ts,
+    )?;
+    printer.finish()?;
+    args.check(&diagnostics)
+}
+
+/// Render and write each item through the compositor.
+/// Errors from the search are reported to `diagnostics` without stopping, only write errors are returned.
+pub fn print_items<W: Compositor<Context = ItemMatch> + ?Sized>(
+    output: &mut W,
+    items: impl Iterator<Item = Result<ItemMatch>>,
+    diagnostics: &mut Diagnostics,
+    options: &RenderOptions,
+    ps: &SyntaxSet,
+    ts: &ThemeSet,
+) -> Result<()> {
+    let syntax = rust_syntax(ps)?;
+    let theme = options.theme(ts)?;
+    for item in items {
+        let item = match item {
+            Ok(item) => item,
+            Err(e) => {
+                diagnostics.report(&e);
+                continue;
             }
-            _ => {}
-        }
+        };
+        let rendered = if output.renders() {
+            render_item(&item, options, syntax, theme, ps)
+        } else {
+            String::new()
+        };
+        output.write_with(format_args!("{}", rendered), item)?;
+        output.flush()?;
     }
     Ok(())
 }
 
+pub fn rust_syntax(ps: &SyntaxSet) -> Result<&SyntaxReference> {
+    ps.find_syntax_by_extension("rs")
+        .ok_or_else(|| eyre::eyre!("Syntax highlight support was not found for rust files"))
+}
+
 pub fn is_rust_file(dir_entry: &DirEntry) -> bool {
     dir_entry.metadata().map(|m| !m.is_dir()).unwrap_or(false)
         && dir_entry.path().extension().unwrap_or_default() == "rs"
 }
 
-use lazy_static::lazy_static;
-
-struct GrepResult {
-    line_range: (usize, usize),
-    item_type: ItemType,
-    writer: BufWriter<Vec<u8>>,
-}
-
-lazy_static! {
-    static ref LINE_REGEX: regex::Regex = regex::RegexBuilder::new("(.*\r?\n?)")
-        .multi_line(true)
-        .build()
-        .unwrap();
-}
-pub fn grep_items<W: Compositor<Context = ((usize, usize), ItemType)>>(
+/// Search a single file, highlighting its items in parallel.
+/// Matches found by the fallback search are still written when the file fails to parse,
+/// and the [`ParseError`] is returned afterwards.
+pub fn grep_items<W: Compositor<Context = ItemMatch> + ?Sized>(
     output: &mut W,
     file: &ParsedFile,
-    re: &Regex,
+    searcher: &Searcher,
+    options: &RenderOptions,
     syntax: &SyntaxReference,
+    theme: &Theme,
     ps: &SyntaxSet,
-    ts: &ThemeSet,
-) {
-    let syn_file: syn::File;
-    match syn::parse_file(&file.contents) {
-        Ok(f) => syn_file = f,
-        Err(_) => return,
-    };
-    // Indexes for the starting byte offset for a given line.
-    // byte_spans[i]..byte_spans[i+1] = byte range for a line in a file.
-    let mut byte_spans = vec![0usize];
-    byte_spans.extend(LINE_REGEX.find_iter(&file.contents).scan(0, |acc, l| {
-        *acc += l.as_str().len();
-        Some(*acc)
-    }));
-    assert_eq!(
-        byte_spans.last().unwrap(),
-        &file.contents.len(),
-        "{}",
-        file.dir_entry.path().display()
-    );
-    let (tx, rx) = crossbeam::channel::unbounded();
+) -> Result<()> {
+    let renders = output.renders();
+    let (items, error) = searcher.search_file(file);
     use rayon::prelude::*;
-    syn_file
-        .items
-        .iter()
+    // Collecting keeps the items in source order, however rayon splits the work.
+    let rendered: Vec<(ItemMatch, String)> = items
+        .into_par_iter()
         .map(|item| {
-            let span = item.span();
-            let (start, end) = (span.start().line, span.end().line);
-            let item_type = item_type(item);
-            (item_type, (start, end))
+            let rendered = if renders {
+                render_item(&item, options, syntax, theme, ps)
+            } else {
+                String::new()
+            };
+            (item, rendered)
         })
-        .collect::<Vec<(ItemType, (usize, usize))>>()
-        .into_par_iter()
-        .for_each(|(t, (start, end))| {
-            let string = Vec::new();
-            let mut writer = std::io::BufWriter::new(string);
-            // println!("{} {} {}", file.dir_entry.path().display(), start, end);
-            let span_start: usize = byte_spans[start];
-            let span_end: usize = byte_spans[end];
-            let item = &file.contents[span_start..span_end];
-            if byte_spans.len() > 100 {
-                // TODO
-                for m in re.find_iter(item) {
-                    match byte_spans.binary_search(&m.start()) {
-                        Ok(_i) => {}  // The match is at a new line
-                        Err(_i) => {} // The match is somewhere in i - 1 (?)
-                    }
-                }
-            } else if re.is_match(item) {
-                let mut h =
-                    syntect::easy::HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
-                // Write highlighted strings to buffer.
-                for line in LinesWithEndings::from(item) {
-                    let mut ranges: Vec<(Style, &str)> = h.highlight(line, ps);
-                    highlight_matches_in_line(&mut ranges, re.find_iter(line));
-                    let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
-                    write!(writer, "{}", escaped).unwrap();
-                }
-                writeln!(writer, "\x1b[0m").unwrap();
-                let grep_result = GrepResult {
-                    item_type: t,
-                    line_range: (start, end),
-                    writer,
-                };
-                tx.send(grep_result).unwrap();
-            }
-        });
-    // Drop the unused tx after sending them to rayon iters.
-    drop(tx);
-
-    while let Ok(GrepResult {
-        writer,
-        line_range,
-        item_type,
-    }) = rx.recv()
-    {
-        let string = String::from_utf8(writer.into_inner().unwrap()).unwrap();
-        output
-            .write_with(format_args!("{}", string), (line_range, item_type))
-            .unwrap();
-        output.flush().unwrap();
+        .collect();
+
+    for (item, rendered) in rendered {
+        output.write_with(format_args!("{}", rendered), item)?;
+        output.flush()?;
     }
+    error.map_or(Ok(()), |e| Err(e.into()))
 }
 
-const HIGHLIGHT_COLOR: Color = Color {
-    r: 255,
-    g: 255,
-    b: 80,
-    a: 255,
-};
-
-/// Modify the output vec to highlight the regex matches found in the iter.
-pub fn highlight_matches_in_line(ranges: &mut Vec<(Style, &str)>, line_matches: regex::Matches) {
-    // Index ranges for currently styled slices.
-    let mut rs = vec![0usize];
-    rs.extend(ranges.iter().scan(0, |acc, (_, slice)| {
-        *acc += slice.len();
-        Some(*acc)
-    }));
+/// Highlight the source of an item, marking its matches.
+pub fn render_item(
+    item: &ItemMatch,
+    options: &RenderOptions,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    ps: &SyntaxSet,
+) -> String {
+    let source = options.source(item);
+    let lines: Vec<&str> = LinesWithEndings::from(&source.text).collect();
+    // Offsets of each line within the item source.
+    let line_starts: Vec<usize> = lines
+        .iter()
+        .scan(0, |acc, line| {
+            let start = *acc;
+            *acc += line.len();
+            Some(start)
+        })
+        .collect();
+    let width = item.line_range.1.to_string().len();
+    let folds = match options.context {
+        Some(context) if item.item_type != ItemType::Line => {
+            let match_lines: Vec<usize> = source
+                .matches
+                .iter()
+                .map(|m| line_starts.partition_point(|&start| start <= m.start) - 1)
+                .collect();
+            fold::folds(
+                item.item_type,
+                &source.text,
+                source.item_range.clone(),
+                &match_lines,
+                context,
+            )
+        }
+        _ => Vec::new(),
+    };
 
-    for m in line_matches {
-        match rs.binary_search(&m.start()) {
-            Ok(index) => {
-                ranges[index].0.font_style = FontStyle::BOLD;
-                ranges[index].0.foreground = HIGHLIGHT_COLOR;
+    let palette = options.palette();
+    let mut h = syntect::easy::HighlightLines::new(syntax, theme);
+    let mut out = String::new();
+    for (i, (line, line_start)) in lines.into_iter().zip(line_starts).enumerate() {
+        if let Some(fold) = folds.iter().find(|fold| fold.contains(&i)) {
+            // The highlighter still needs to see the folded lines to keep track of its state.
+            if palette != Palette::Plain {
+                h.highlight(line, ps);
+            }
+            if fold.start == i {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let marker = format!("{}\u{22ef} {} lines \u{22ef}\n", indent, fold.len());
+                out.push_str(&render_marker(&marker, options, width, theme));
+            }
+            continue;
+        }
+        let line_end = line_start + line.len();
+        let in_line = |r: &Range<usize>| r.start < line_end && r.end > line_start;
+        let line_matches: Vec<Range<usize>> = source
+            .matches
+            .iter()
+            .filter(|r| in_line(r))
+            .map(|r| r.start.max(line_start) - line_start..r.end.min(line_end) - line_start)
+            .collect();
+        let gutter = if options.no_line_number {
+            String::new()
+        } else {
+            // Reformatted lines only get the number of the line their first match came from.
+            let number = match source.first_line {
+                Some(first) => Some(first + i),
+                None => source
+                    .matches
+                    .iter()
+                    .position(in_line)
+                    .map(|m| item.matches[m].line),
+            };
+            let mark = if line_matches.is_empty() { '-' } else { ':' };
+            match number {
+                Some(number) => format!("{:>width$}{} ", number, mark, width = width),
+                None => format!("{:>width$}{} ", "", mark, width = width),
             }
-            Err(new_index) => {
-                if rs[new_index] == m.end() {
-                    // Found end of target string.
-                    if rs.get(new_index - 1).is_some() {
-                        ranges[new_index - 1].0.font_style = FontStyle::BOLD;
-                        ranges[new_index - 1].0.foreground = HIGHLIGHT_COLOR;
-                    }
+        };
+        if palette == Palette::Plain {
+            out.push_str(&gutter);
+            out.push_str(line);
+            if !line_matches.is_empty() {
+                if !line.ends_with('\n') {
+                    out.push('\n');
                 }
+                out.push_str(&" ".repeat(gutter.len()));
+                out.push_str(&match_markers(line, &line_matches));
             }
+            continue;
+        }
+        let mut ranges: Vec<(Style, &str)> = h.highlight(line, ps);
+        highlight_matches_in_line(
+            &mut ranges,
+            line_matches.iter().cloned(),
+            options.match_style(),
+        );
+        if !gutter.is_empty() {
+            let style = if line_matches.is_empty() {
+                options.gutter_style(theme)
+            } else {
+                options.gutter_style(theme).apply(options.match_style())
+            };
+            ranges.insert(0, (style, &gutter));
         }
+        out.push_str(&palette.escape(&ranges[..], theme.settings.background));
+    }
+    if source.elided_matches > 0 {
+        let marker = format!(
+            "    \u{22ef} {} in the body \u{22ef}\n",
+            match source.elided_matches {
+                1 => "1 match".to_string(),
+                n => format!("{} matches", n),
+            }
+        );
+        out.push_str(&render_marker(&marker, options, width, theme));
+    }
+    if palette != Palette::Plain {
+        out.push_str("\x1b[0m");
+    }
+    out.push('\n');
+    out
+}
+
+/// A line standing in for the parts of an item left out, with an empty gutter.
+fn render_marker(marker: &str, options: &RenderOptions, width: usize, theme: &Theme) -> String {
+    let gutter = if options.no_line_number {
+        String::new()
+    } else {
+        " ".repeat(width + 2)
+    };
+    let palette = options.palette();
+    if palette == Palette::Plain {
+        return gutter + marker;
+    }
+    let style = options.gutter_style(theme);
+    let ranges = [
+        (style, gutter.as_str()),
+        (
+            Style {
+                background: theme.settings.background.unwrap_or(style.background),
+                ..style
+            },
+            marker,
+        ),
+    ];
+    palette.escape(&ranges, theme.settings.background)
+}
+
+/// Modify the output vec to highlight the given byte ranges of the line.
+pub fn highlight_matches_in_line(
+    ranges: &mut Vec<(Style, &str)>,
+    line_matches: impl IntoIterator<Item = Range<usize>>,
+    modifier: StyleModifier,
+) {
+    for m in line_matches {
+        *ranges = modify_range(ranges, m, modifier);
     }
 }
@@ -1,5 +1,5 @@
 use clap::Clap;
-use rgrok::{parallel::rgrok_dir_parallel, rgrok_dir, Args};
+use rgrok::{parallel::rgrok_dir_parallel, rgrok_dir, Args, Mode};
 
 use color_eyre::Result;
 use syntect::highlighting::ThemeSet;
@@ -9,10 +9,14 @@ fn main() -> Result<()> {
     color_eyre::install()?;
     // Load these once at the start of your program
     let ps = SyntaxSet::load_defaults_newlines();
-    let ts = ThemeSet::load_defaults();
+    let mut ts = ThemeSet::load_defaults();
 
     let args = Args::parse();
+    args.render.load_themes(&mut ts)?;
 
+    if let Some(Mode::Index(index)) = &args.mode {
+        return index.run(args.root());
+    }
     if args.parallel {
         rgrok_dir_parallel(args, &ps, &ts)
     } else {
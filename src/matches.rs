@@ -0,0 +1,73 @@
+use crate::util::ItemType;
+
+use std::ops::Range;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+/// An item containing at least one match.
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemMatch {
+    pub path: PathBuf,
+    pub item_type: ItemType,
+    pub ident: Option<String>,
+    /// Declaration of the item without its body, e.g. `pub fn connect(addr: &str) -> Result<Client>`.
+    /// Lines from files that failed to parse have none.
+    pub signature: Option<String>,
+    /// Byte offset in the file of the part of the item its signature leaves out, such as the block of a fn.
+    /// Matches from there on are in the body.
+    pub body_start: Option<usize>,
+    /// Module containing the item, from the file location and any inline modules, e.g. `["crate", "net", "client"]`.
+    pub module_path: Vec<String>,
+    /// Labels of the enclosing items and the item itself, e.g. `["impl Client", "fn connect"]`.
+    pub scope: Vec<String>,
+    /// 1-indexed, inclusive line range of the item.
+    pub line_range: (usize, usize),
+    /// Byte range of the item in the file.
+    pub byte_range: (usize, usize),
+    /// 1-indexed line of the item's name, or of the implementing type for impls, past any attributes.
+    pub name_line: Option<usize>,
+    pub matches: Vec<MatchSpan>,
+    /// Source of the lines spanned by the item.
+    #[serde(skip)]
+    pub source: String,
+    /// Byte offset of `source` in the file.
+    #[serde(skip)]
+    pub source_start: usize,
+}
+
+impl ItemMatch {
+    /// Where the item lives, e.g. `crate::net::client > impl Client > fn connect`.
+    pub fn breadcrumb(&self) -> String {
+        std::iter::once(self.module_path.join("::"))
+            .chain(self.scope.iter().cloned())
+            .collect::<Vec<String>>()
+            .join(" > ")
+    }
+
+    /// Byte ranges of the matches within `source`.
+    pub fn source_ranges(&self) -> Vec<Range<usize>> {
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(self.source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        self.matches
+            .iter()
+            .map(|m| {
+                let start = line_starts[m.line - self.line_range.0] + m.column - 1;
+                start..start + m.text.len()
+            })
+            .collect()
+    }
+}
+
+/// A single regex match within an item.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchSpan {
+    /// 1-indexed line of the start of the match.
+    pub line: usize,
+    /// 1-indexed byte column of the start of the match.
+    pub column: usize,
+    /// Byte range of the match in the file.
+    pub byte_range: (usize, usize),
+    pub text: String,
+}
@@ -0,0 +1,103 @@
+use crate::matches::ItemMatch;
+use crate::Compositor;
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::Clap;
+
+/// List every item in the tree instead of searching, like ctags.
+#[derive(Clap, Clone)]
+pub struct Outline {
+    /// Directory or file to list, instead of --path.
+    pub path: Option<PathBuf>,
+    /// Print one tab separated line per item instead of an indented tree.
+    #[clap(long)]
+    pub flat: bool,
+}
+
+/// Writes each item as a single line with its kind, line range and signature.
+pub struct OutlinePrinter<W: Write> {
+    output: W,
+    flat: bool,
+    /// File of the last item written.
+    path: Option<PathBuf>,
+    /// Byte ranges of the items written so far that enclose the last one, outermost first.
+    enclosing: Vec<(usize, usize)>,
+}
+
+impl<W: Write> OutlinePrinter<W> {
+    pub fn new(output: W, flat: bool) -> Self {
+        Self {
+            output,
+            flat,
+            path: None,
+            enclosing: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for OutlinePrinter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<W: Write> Compositor for OutlinePrinter<W> {
+    type Context = ItemMatch;
+
+    fn renders(&self) -> bool {
+        false
+    }
+
+    fn write_with(
+        &mut self,
+        _: std::fmt::Arguments,
+        item: Self::Context,
+    ) -> std::result::Result<(), std::io::Error> {
+        let (start, end) = item.line_range;
+        let signature = item.signature.as_deref().unwrap_or_default();
+        if self.flat {
+            return writeln!(
+                self.output,
+                "{}:{}-{}\t{}\t{}\t{}",
+                item.path.display(),
+                start,
+                end,
+                item.item_type,
+                item.ident.as_deref().unwrap_or_default(),
+                signature
+            );
+        }
+
+        if self.path.as_ref() != Some(&item.path) {
+            writeln!(self.output, "{}", item.path.display())?;
+            self.path = Some(item.path.clone());
+            self.enclosing.clear();
+        }
+        // Items of a file arrive in pre-order, so the enclosing items are a stack.
+        let (item_start, item_end) = item.byte_range;
+        while let Some(&(parent_start, parent_end)) = self.enclosing.last() {
+            if parent_start <= item_start && item_end <= parent_end {
+                break;
+            }
+            self.enclosing.pop();
+        }
+        writeln!(
+            self.output,
+            "{:indent$}[{}] {} {}-{}",
+            "",
+            item.item_type,
+            signature,
+            start,
+            end,
+            indent = 2 * (self.enclosing.len() + 1)
+        )?;
+        self.enclosing.push(item.byte_range);
+        Ok(())
+    }
+}
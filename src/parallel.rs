@@ -1,97 +1,134 @@
 use crate::grep_items;
 use crate::is_rust_file;
 use crate::parse_file;
-
+use crate::render::RenderOptions;
+use crate::rust_syntax;
+use crate::search::{ParseError, Searcher, SortBy};
 
 use crate::util::ParsedFile;
-use crate::Args;
-
-
-
-
+use crate::{matches::ItemMatch, Compositor};
+use crate::{Args, Diagnostics};
 
-
-use color_eyre::{
-    eyre::{self, Context},
-    Result,
-};
-use crossbeam::channel::{Sender};
+use color_eyre::Result;
+use crossbeam::channel::Sender;
 
 use ignore::{DirEntry, ParallelVisitor, ParallelVisitorBuilder, WalkBuilder};
 
+use syntect::{highlighting::ThemeSet, parsing::SyntaxSet};
 
-use syntect::{
-    highlighting::{ThemeSet},
-    parsing::{SyntaxReference, SyntaxSet},
-};
-
-
+/// How many parsed files can wait to be searched, which bounds the memory used ahead of the output.
+const CHANNEL_CAPACITY: usize = 64;
 
-
-pub fn rgrok_dir_parallel(mut args: Args, ps: &SyntaxSet, ts: &ThemeSet) -> Result<()> {
-    let walker = WalkBuilder::new(args.path).threads(0).build_parallel();
+/// Walk the tree on several threads, searching and printing the files on this one as they arrive.
+/// Errors from the walkers are sent back alongside the parsed files, so one bad file doesn't stop the search.
+pub fn rgrok_dir_parallel(args: Args, ps: &SyntaxSet, ts: &ThemeSet) -> Result<()> {
+    let searcher = args.searcher()?;
+    let walker = WalkBuilder::new(args.root()).threads(0).build_parallel();
 
     struct Visitor<'a> {
-        tx: Sender<(ParsedFile, SyntaxReference)>,
-        ps: &'a SyntaxSet,
-        re: &'a regex::Regex,
+        tx: Sender<Result<ParsedFile>>,
+        re: Option<&'a regex::Regex>,
     }
     impl<'a> ParallelVisitor for Visitor<'a> {
         fn visit(&mut self, entry: Result<DirEntry, ignore::Error>) -> ignore::WalkState {
             use ignore::WalkState::*;
-            match entry {
-                Ok(dir_entry) => {
-                    if is_rust_file(&dir_entry) {
-                        let syntax = self
-                            .ps
-                            .find_syntax_for_file(dir_entry.path())
-                            .wrap_err(eyre::eyre!(
-                            "Syntax highlight support was not found for the following file: {:?}",
-                            dir_entry.path()
-                            ))
-                            .unwrap()
-                            .unwrap();
-                        let file = parse_file(dir_entry).unwrap();
-                        if self.re.is_match(&file.contents) {
-                            self.tx.send((file, syntax.clone())).unwrap();
-                        }
-                    }
-                    Continue
-                }
-                Err(_) => Continue,
+            let message = match entry {
+                Ok(dir_entry) if is_rust_file(&dir_entry) => match parse_file(dir_entry) {
+                    Ok(file) if self.re.is_none_or(|re| re.is_match(&file.contents)) => Ok(file),
+                    Ok(_) => return Continue,
+                    Err(e) => Err(e),
+                },
+                Ok(_) => return Continue,
+                Err(e) => Err(e.into()),
+            };
+            match self.tx.send(message) {
+                Ok(()) => Continue,
+                // Nobody is listening anymore.
+                Err(_) => Quit,
             }
         }
     }
     struct VisitorBuilder<'a> {
-        ps: &'a SyntaxSet,
-        re: &'a regex::Regex,
-        tx: Sender<(ParsedFile, SyntaxReference)>,
+        re: Option<&'a regex::Regex>,
+        tx: Sender<Result<ParsedFile>>,
     }
     impl<'s> ParallelVisitorBuilder<'s> for VisitorBuilder<'s> {
         fn build(&mut self) -> Box<dyn ignore::ParallelVisitor + 's> {
             Box::new(Visitor {
                 tx: self.tx.clone(),
-                ps: self.ps,
                 re: self.re,
             })
         }
     }
 
-    let (tx, rx) = crossbeam::channel::unbounded::<(ParsedFile, SyntaxReference)>();
-
-    {
-        let mut vbuilder = VisitorBuilder {
-            ps,
-            re: &args.regex,
-            tx,
+    let (tx, rx) = crossbeam::channel::bounded::<Result<ParsedFile>>(CHANNEL_CAPACITY);
+    let mut printer = args.printer()?;
+    let mut diagnostics = Diagnostics::default();
+
+    std::thread::scope(|scope| {
+        // The walkers block once the channel is full, until the files ahead of them have been printed.
+        scope.spawn(|| {
+            // Files without a match are skipped before parsing, unless parse failures matter.
+            let mut vbuilder = VisitorBuilder {
+                re: args.pattern().filter(|_| !args.strict),
+                tx,
+            };
+            walker.visit(&mut vbuilder);
+        });
+
+        // Sorting needs every file, so it waits for the walk to finish.
+        let messages: Box<dyn Iterator<Item = Result<ParsedFile>>> = match args.sort {
+            SortBy::None => Box::new(rx.into_iter()),
+            sort => {
+                let mut messages: Vec<Result<ParsedFile>> = rx.into_iter().collect();
+                sort.sort(&mut messages, |message| {
+                    message.as_ref().ok().map(|file| &file.dir_entry)
+                });
+                Box::new(messages.into_iter())
+            }
         };
-        walker.visit(&mut vbuilder);
-        // Drop that vbuilder
-    }
+        // Dropping the receiver on an error makes the walkers quit, so the scope can end.
+        drain(
+            &mut *printer,
+            messages,
+            &searcher,
+            &mut diagnostics,
+            &args.render,
+            ps,
+            ts,
+        )
+    })?;
+    printer.finish()?;
+    args.check(&diagnostics)
+}
 
-    while let Ok((file, syntax)) = rx.recv() {
-        grep_items(&mut args.output, &file, &args.regex, &syntax, ps, ts)
+/// Search the files received from the walkers, reporting any errors they sent.
+/// Only errors writing the output stop the search.
+fn drain<W: Compositor<Context = ItemMatch> + ?Sized>(
+    output: &mut W,
+    messages: impl Iterator<Item = Result<ParsedFile>>,
+    searcher: &Searcher,
+    diagnostics: &mut Diagnostics,
+    options: &RenderOptions,
+    ps: &SyntaxSet,
+    ts: &ThemeSet,
+) -> Result<()> {
+    let syntax = rust_syntax(ps)?;
+    let theme = options.theme(ts)?;
+    for message in messages {
+        let file = match message {
+            Ok(file) => file,
+            Err(e) => {
+                diagnostics.report(&e);
+                continue;
+            }
+        };
+        if let Err(e) = grep_items(output, &file, searcher, options, syntax, theme, ps) {
+            match e.downcast_ref::<ParseError>() {
+                Some(_) => diagnostics.report(&e),
+                None => return Err(e),
+            }
+        }
     }
-
     Ok(())
 }
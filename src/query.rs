@@ -0,0 +1,481 @@
+//! A small query language over item signatures.
+//!
+//! A query names a kind of item, optionally followed by constraints on its signature:
+//!
+//! ```text
+//! fn(ret: Result<_, *>)
+//! fn(self: &mut self, arg: &str)
+//! struct(field: Arc<Mutex<*>>)
+//! impl(trait: Display, for: Wrapper<_>)
+//! ```
+//!
+//! In type patterns `_` stands for exactly one type and `*` for anything, including nothing.
+//! Paths in the pattern may leave out leading segments, so `io::Error` matches `std::io::Error`.
+//! A trait or type without generic arguments in an impl query matches any, so `From` matches `From<T>`.
+
+use crate::util::{tokens_to_string, ItemType};
+use crate::visit::{ItemNode, ItemSpan, Node};
+
+use std::str::FromStr;
+
+use color_eyre::{eyre, Report, Result};
+use serde::{Deserialize, Serialize};
+use syn::{Fields, FnArg, ForeignItem, ImplItem, Item, ReturnType, Signature, TraitItem};
+
+#[derive(Debug, Clone)]
+pub struct Query {
+    /// Either an item keyword such as `fn`, which covers methods too, or an item kind name.
+    kind: String,
+    constraints: Vec<Constraint>,
+}
+
+#[derive(Debug, Clone)]
+enum Constraint {
+    /// The return type of a fn, `()` if there is none.
+    Ret(TypePattern),
+    /// The type of any of the arguments of a fn.
+    Arg(TypePattern),
+    /// The receiver of a method, e.g. `&mut self`.
+    Receiver(TypePattern),
+    /// The type of any field of a struct, enum or union.
+    Field(TypePattern),
+    /// The trait being implemented.
+    Trait(TypePattern),
+    /// The type an impl is for.
+    For(TypePattern),
+}
+
+impl FromStr for Query {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (kind, body) = match s.find('(') {
+            Some(open) => {
+                let body = s[open + 1..]
+                    .strip_suffix(')')
+                    .ok_or_else(|| eyre::eyre!("Unclosed '(' in query {:?}", s))?;
+                (s[..open].trim(), body)
+            }
+            None => (s, ""),
+        };
+        if !ItemType::ALL
+            .iter()
+            .any(|t| t.keyword() == kind || t.name() == kind)
+        {
+            return Err(eyre::eyre!("Unknown item kind {:?} in query", kind));
+        }
+        let constraints = split_top_level(body)
+            .into_iter()
+            .filter(|c| !c.trim().is_empty())
+            .map(|c| parse_constraint(kind, c))
+            .collect::<Result<Vec<Constraint>>>()?;
+        Ok(Query {
+            kind: kind.to_string(),
+            constraints,
+        })
+    }
+}
+
+fn parse_constraint(kind: &str, constraint: &str) -> Result<Constraint> {
+    let (key, pattern) = constraint
+        .split_once(':')
+        .ok_or_else(|| eyre::eyre!("Expected `key: pattern` in query, found {:?}", constraint))?;
+    let pattern = TypePattern::new(pattern);
+    let constraint = match (kind, key.trim()) {
+        ("fn", "ret") => Constraint::Ret(pattern),
+        ("fn", "arg") => Constraint::Arg(pattern),
+        ("fn", "self") => Constraint::Receiver(pattern),
+        ("struct" | "enum" | "union", "field") => Constraint::Field(pattern),
+        ("impl", "trait") => Constraint::Trait(pattern.any_generics()),
+        ("impl", "for") => Constraint::For(pattern.any_generics()),
+        (kind, key) => {
+            return Err(eyre::eyre!(
+                "Queries on {:?} items can't constrain {:?}",
+                kind,
+                key
+            ))
+        }
+    };
+    Ok(constraint)
+}
+
+impl Query {
+    /// Impls of a trait, for a type, or both.
+    pub fn impls(trait_: Option<&str>, for_: Option<&str>) -> Self {
+        let constraints = trait_
+            .map(|p| Constraint::Trait(TypePattern::new(p).any_generics()))
+            .into_iter()
+            .chain(for_.map(|p| Constraint::For(TypePattern::new(p).any_generics())))
+            .collect();
+        Query {
+            kind: ItemType::Impl.name().to_string(),
+            constraints,
+        }
+    }
+
+    pub fn matches<N: Node>(&self, item: &ItemSpan<N>) -> bool {
+        (item.item_type.keyword() == self.kind || item.item_type.name() == self.kind) && {
+            let shape = item.node.shape();
+            self.constraints.iter().all(|c| c.matches(&shape))
+        }
+    }
+}
+
+impl Constraint {
+    fn matches(&self, shape: &Shape) -> bool {
+        let any =
+            |pattern: &TypePattern, types: &[String]| types.iter().any(|ty| pattern.matches(ty));
+        match self {
+            Constraint::Ret(pattern) => shape.ret.as_ref().is_some_and(|ty| pattern.matches(ty)),
+            Constraint::Arg(pattern) => any(pattern, &shape.args),
+            Constraint::Receiver(pattern) => shape
+                .receiver
+                .as_ref()
+                .is_some_and(|ty| pattern.matches(ty)),
+            Constraint::Field(pattern) => any(pattern, &shape.fields),
+            Constraint::Trait(pattern) => {
+                shape.trait_.as_ref().is_some_and(|ty| pattern.matches(ty))
+            }
+            Constraint::For(pattern) => {
+                shape.self_ty.as_ref().is_some_and(|ty| pattern.matches(ty))
+            }
+        }
+    }
+}
+
+/// The types in the declaration of an item that a query can constrain, as source text.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Shape {
+    /// The return type of a fn, `()` if there is none.
+    pub ret: Option<String>,
+    /// The types of the arguments of a fn, besides the receiver.
+    pub args: Vec<String>,
+    /// The receiver of a method, e.g. `&mut self` or `Box<Self>`.
+    pub receiver: Option<String>,
+    /// The types of the fields of a struct, union or of any variant of an enum.
+    pub fields: Vec<String>,
+    /// The trait an impl implements.
+    pub trait_: Option<String>,
+    /// The type an impl is for.
+    pub self_ty: Option<String>,
+}
+
+impl Shape {
+    pub fn of(node: &ItemNode) -> Self {
+        let mut shape = Shape::default();
+        if let Some(sig) = signature(node) {
+            shape.ret = Some(match &sig.output {
+                ReturnType::Default => "()".to_string(),
+                ReturnType::Type(_, ty) => tokens_to_string(ty),
+            });
+            shape.args = sig
+                .inputs
+                .iter()
+                .filter_map(|arg| match arg {
+                    FnArg::Typed(arg) => Some(tokens_to_string(&arg.ty)),
+                    FnArg::Receiver(_) => None,
+                })
+                .collect();
+            shape.receiver = sig.receiver().map(|receiver| match receiver {
+                FnArg::Receiver(receiver) => tokens_to_string(receiver),
+                // `self: Box<Self>`
+                FnArg::Typed(arg) => tokens_to_string(&arg.ty),
+            });
+        }
+        let fields: Vec<&Fields> = match node {
+            ItemNode::Item(Item::Struct(item)) => vec![&item.fields],
+            ItemNode::Item(Item::Enum(item)) => item.variants.iter().map(|v| &v.fields).collect(),
+            _ => Vec::new(),
+        };
+        shape.fields = fields
+            .into_iter()
+            .flat_map(|fields| fields.iter())
+            .map(|f| tokens_to_string(&f.ty))
+            .collect();
+        match node {
+            ItemNode::Item(Item::Union(item)) => {
+                shape.fields = item
+                    .fields
+                    .named
+                    .iter()
+                    .map(|f| tokens_to_string(&f.ty))
+                    .collect()
+            }
+            ItemNode::Item(Item::Impl(item)) => {
+                shape.trait_ = item
+                    .trait_
+                    .as_ref()
+                    .map(|(_, path, _)| tokens_to_string(path));
+                shape.self_ty = Some(tokens_to_string(&item.self_ty));
+            }
+            _ => {}
+        }
+        shape
+    }
+}
+
+fn signature<'a>(node: &ItemNode<'a>) -> Option<&'a Signature> {
+    match *node {
+        ItemNode::Item(Item::Fn(f)) => Some(&f.sig),
+        ItemNode::ImplItem(ImplItem::Method(m)) => Some(&m.sig),
+        ItemNode::TraitItem(TraitItem::Method(m)) => Some(&m.sig),
+        ItemNode::ForeignItem(ForeignItem::Fn(f)) => Some(&f.sig),
+        _ => None,
+    }
+}
+
+/// A pattern over the tokens of a type.
+#[derive(Debug, Clone)]
+struct TypePattern {
+    tokens: Vec<String>,
+    /// Also match the type followed by any generic arguments.
+    any_generics: bool,
+}
+
+impl TypePattern {
+    fn new(pattern: &str) -> Self {
+        Self {
+            tokens: lex(pattern),
+            any_generics: false,
+        }
+    }
+
+    /// Let a pattern ending in a path with no generic arguments match any, e.g. `From` matches `From<T>`.
+    fn any_generics(mut self) -> Self {
+        self.any_generics = self.tokens.last().is_some_and(|t| is_ident(t));
+        self
+    }
+
+    fn matches(&self, ty: &str) -> bool {
+        let ty = lex(ty);
+        match_tokens(&self.tokens, &ty)
+            || (self.any_generics && {
+                let generic: Vec<String> = self
+                    .tokens
+                    .iter()
+                    .cloned()
+                    .chain(["<", "*", ">"].iter().map(|t| t.to_string()))
+                    .collect();
+                match_tokens(&generic, &ty)
+            })
+    }
+}
+
+/// Split a type into identifiers, `::`, `->` and single punctuation characters.
+fn lex(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c.is_alphanumeric() || c == '_' {
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = j + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(s[i..end].to_string());
+        } else if (c == ':' || c == '-') && s[i + 1..].starts_with(if c == ':' { ':' } else { '>' })
+        {
+            chars.next();
+            tokens.push(s[i..i + 2].to_string());
+        } else {
+            tokens.push(c.to_string());
+        }
+    }
+    tokens
+}
+
+fn is_ident(token: &str) -> bool {
+    token.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Whether the brackets in the tokens are balanced.
+/// With `single`, also require that there is no top level `,`, i.e. the tokens form a single type.
+fn balanced(tokens: &[String], single: bool) -> bool {
+    let mut depth = 0i32;
+    for token in tokens {
+        match token.as_str() {
+            "<" | "(" | "[" => depth += 1,
+            ">" | ")" | "]" => depth -= 1,
+            "," if single && depth == 0 => return false,
+            _ => {}
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    depth == 0
+}
+
+fn match_tokens(pattern: &[String], ty: &[String]) -> bool {
+    match_from(pattern, ty, true)
+}
+
+/// `path_start` is false right after a `::` of the pattern, where segments of the type can't be skipped.
+fn match_from(pattern: &[String], ty: &[String], path_start: bool) -> bool {
+    match pattern.first().map(String::as_str) {
+        None => ty.is_empty(),
+        Some("*") => (0..=ty.len())
+            .any(|k| balanced(&ty[..k], false) && match_tokens(&pattern[1..], &ty[k..])),
+        Some("_") => (1..=ty.len())
+            .any(|k| balanced(&ty[..k], true) && match_tokens(&pattern[1..], &ty[k..])),
+        Some(token) => {
+            (ty.first().map(String::as_str) == Some(token)
+                && match_from(&pattern[1..], &ty[1..], token != "::"))
+                // Skip a leading path segment of the type, e.g. `std::` in `std::io::Error`.
+                || (path_start
+                    && is_ident(token)
+                    && ty.len() >= 2
+                    && is_ident(&ty[0])
+                    && ty[1] == "::"
+                    && match_from(pattern, &ty[2..], true))
+        }
+    }
+}
+
+/// Split on commas that aren't nested in brackets.
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            // Not the arrow of a return type.
+            '>' if s[..i].ends_with('-') => {}
+            '>' | ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visit::collect_items;
+
+    fn pattern_matches(pattern: &str, ty: &str) -> bool {
+        TypePattern::new(pattern).matches(ty)
+    }
+
+    /// The names of the items of `source` that `query` matches.
+    fn matching(query: &str, source: &str) -> Vec<String> {
+        let query: Query = query.parse().unwrap();
+        let file = syn::parse_file(source).unwrap();
+        collect_items(&file.items)
+            .iter()
+            .filter(|item| query.matches(item))
+            .map(|item| item.label.clone())
+            .collect()
+    }
+
+    #[test]
+    fn lex_types() {
+        assert_eq!(
+            lex("std::io::Result<&'a mut [u8]>"),
+            ["std", "::", "io", "::", "Result", "<", "&", "'", "a", "mut", "[", "u8", "]", ">"]
+        );
+        assert_eq!(lex("fn(u8) -> ()"), ["fn", "(", "u8", ")", "->", "(", ")"]);
+        assert_eq!(lex(" a : b "), ["a", ":", "b"]);
+    }
+
+    #[test]
+    fn split_at_top_level_commas() {
+        assert_eq!(
+            split_top_level("ret: Result<_, *>, arg: (u8, u8)"),
+            ["ret: Result<_, *>", " arg: (u8, u8)"]
+        );
+        assert_eq!(
+            split_top_level("arg: fn(u8) -> u8, ret: ()"),
+            ["arg: fn(u8) -> u8", " ret: ()"]
+        );
+        assert_eq!(split_top_level(""), [""]);
+    }
+
+    #[test]
+    fn underscore_is_exactly_one_type() {
+        assert!(pattern_matches(
+            "Result<_, _>",
+            "Result<Vec<u8>, io::Error>"
+        ));
+        assert!(pattern_matches("Option<_>", "Option<(u8, u8)>"));
+        assert!(!pattern_matches("Option<_>", "Option<>"));
+        assert!(!pattern_matches("Result<_>", "Result<u8, u8>"));
+    }
+
+    #[test]
+    fn star_is_anything() {
+        assert!(pattern_matches("Result<*>", "Result<u8, Error>"));
+        assert!(pattern_matches("Result<*>", "Result<>"));
+        assert!(pattern_matches("Vec<*>", "Vec<HashMap<String, Vec<u8>>>"));
+        assert!(pattern_matches("*", "&mut self"));
+        // `*` can't take half of a bracket pair.
+        assert!(!pattern_matches("Vec<*> >", "Vec<Vec<u8> >"));
+    }
+
+    #[test]
+    fn leading_path_segments_are_optional() {
+        assert!(pattern_matches("io::Error", "std::io::Error"));
+        assert!(pattern_matches("Error", "std::io::Error"));
+        assert!(pattern_matches(
+            "Arc<Mutex<*>>",
+            "std::sync::Arc<std::sync::Mutex<u8>>"
+        ));
+        assert!(!pattern_matches("std::Error", "std::io::Error"));
+        assert!(!pattern_matches("io", "std::io::Error"));
+    }
+
+    #[test]
+    fn any_generics_only_for_bare_paths() {
+        let from = TypePattern::new("From").any_generics();
+        assert!(from.matches("From<u8>"));
+        assert!(from.matches("From"));
+        assert!(!TypePattern::new("From").matches("From<u8>"));
+        let from_u8 = TypePattern::new("From<u8>").any_generics();
+        assert!(!from_u8.any_generics);
+        assert!(!from_u8.matches("From<u16>"));
+    }
+
+    #[test]
+    fn queries_match_items() {
+        let source = "
+            fn plain(x: u8) {}
+            fn fallible(path: &str) -> std::io::Result<String> { todo!() }
+            struct Shared { inner: Arc<Mutex<Vec<u8>>> }
+            impl From<u8> for Shared { fn from(_: u8) -> Self { todo!() } }
+            impl Shared { fn get(&mut self) -> u8 { 0 } }
+        ";
+        assert_eq!(matching("fn(ret: io::Result<*>)", source), ["fn fallible"]);
+        assert_eq!(matching("fn(arg: &str)", source), ["fn fallible"]);
+        assert_eq!(matching("fn(ret: ())", source), ["fn plain"]);
+        assert_eq!(matching("fn(self: &mut self)", source), ["fn get"]);
+        assert_eq!(
+            matching("struct(field: Arc<Mutex<_>>)", source),
+            ["struct Shared"]
+        );
+        assert_eq!(
+            matching("impl(trait: From, for: Shared)", source),
+            ["impl From<u8> for Shared"]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |query: &str| query.parse::<Query>().unwrap_err().to_string();
+        assert!(error("fn(ret: u8").contains("Unclosed"));
+        assert!(error("func").contains("Unknown item kind"));
+        assert!(error("fn(u8)").contains("Expected `key: pattern`"));
+        assert!(error("struct(ret: u8)").contains("can't constrain"));
+    }
+}
@@ -0,0 +1,622 @@
+use crate::matches::{ItemMatch, MatchSpan};
+use crate::rustfmt;
+use crate::util::ItemType;
+
+use std::io::IsTerminal;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use clap::Clap;
+use color_eyre::{eyre, Report, Result};
+use lazy_static::lazy_static;
+use syntect::highlighting::{Color, FontStyle, Style, StyleModifier, Theme, ThemeSet};
+
+/// How matched items are displayed.
+#[derive(Clap, Clone)]
+pub struct RenderOptions {
+    /// Run each item through rustfmt before showing it. Items are shown as written if rustfmt is missing.
+    #[clap(long, global = true)]
+    pub format: bool,
+    /// Syntax highlighting theme, either one of the built in themes, a theme from the theme directory,
+    /// or the path of a `.tmTheme` file.
+    #[clap(long, default_value = DEFAULT_THEME, global = true)]
+    pub theme: String,
+    /// Directory of `.tmTheme` files to choose from with --theme, `~/.config/rgrok/themes` by default.
+    #[clap(long, global = true)]
+    pub theme_dir: Option<PathBuf>,
+    /// Foreground color of matches, as `#rrggbb`.
+    #[clap(long, default_value = "#ffff50", global = true)]
+    pub match_fg: HexColor,
+    /// Background color of matches, as `#rrggbb`. Matches keep the theme's background by default.
+    #[clap(long, global = true)]
+    pub match_bg: Option<HexColor>,
+    /// Font styles of matches: bold, underline, italic or none.
+    #[clap(
+        long,
+        default_value = "bold",
+        use_delimiter = true,
+        multiple_values = false,
+        multiple_occurrences = true,
+        global = true
+    )]
+    pub match_style: Vec<FontFlag>,
+    /// When to use colors: auto, always or never. Auto leaves them out if stdout isn't a terminal or NO_COLOR is set.
+    #[clap(long, default_value = "auto", global = true)]
+    pub color: ColorChoice,
+    /// Leave out the gutter of line numbers, where `:` marks lines with matches and `-` the others.
+    #[clap(short = 'N', long, global = true)]
+    pub no_line_number: bool,
+    /// List the `path:line:column` of each match under its item's header, for editors and terminals to jump to.
+    #[clap(long, global = true)]
+    pub column: bool,
+    /// Only show the signature of each item and the N lines around its matches,
+    /// folding away the statements and members in between.
+    #[clap(short = 'C', long, global = true)]
+    pub context: Option<usize>,
+    /// Show each item as its signature, without the body, and count the matches in the body.
+    #[clap(long, global = true)]
+    pub signatures: bool,
+}
+
+const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            format: false,
+            theme: DEFAULT_THEME.to_string(),
+            theme_dir: None,
+            match_fg: HexColor(Color {
+                r: 255,
+                g: 255,
+                b: 80,
+                a: 255,
+            }),
+            match_bg: None,
+            match_style: vec![FontFlag(FontStyle::BOLD)],
+            color: ColorChoice::Auto,
+            no_line_number: false,
+            column: false,
+            context: None,
+            signatures: false,
+        }
+    }
+}
+
+/// A color given as `#rrggbb`.
+#[derive(Debug, Clone, Copy)]
+pub struct HexColor(pub Color);
+
+impl FromStr for HexColor {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(HexColor(Color { r, g, b, a: 255 })),
+            _ => Err(eyre::eyre!("Invalid color {:?}, expected #rrggbb", s)),
+        }
+    }
+}
+
+/// A font style to give matches.
+#[derive(Debug, Clone, Copy)]
+pub struct FontFlag(pub FontStyle);
+
+impl FromStr for FontFlag {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bold" => Ok(FontFlag(FontStyle::BOLD)),
+            "underline" => Ok(FontFlag(FontStyle::UNDERLINE)),
+            "italic" => Ok(FontFlag(FontStyle::ITALIC)),
+            "none" => Ok(FontFlag(FontStyle::empty())),
+            _ => Err(eyre::eyre!(
+                "Invalid style {:?}, expected one of: bold, underline, italic, none",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(eyre::eyre!(
+                "Invalid color choice {:?}, expected one of: auto, always, never",
+                s
+            )),
+        }
+    }
+}
+
+/// The colors the terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Palette {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+    /// No escapes at all, matches are marked on the line below instead.
+    Plain,
+}
+
+impl Palette {
+    /// Guess the palette from `COLORTERM` and `TERM`, like most terminal programs do.
+    fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            Palette::TrueColor
+        } else if term.contains("256color") {
+            Palette::Ansi256
+        } else {
+            Palette::Ansi16
+        }
+    }
+
+    /// Escape highlighted text for the terminal.
+    /// Backgrounds matching `default_background` are left to the terminal when colors are limited.
+    pub fn escape(&self, ranges: &[(Style, &str)], default_background: Option<Color>) -> String {
+        let mut out = String::new();
+        for (style, text) in ranges {
+            let (fg, bg) = (style.foreground, style.background);
+            match self {
+                Palette::TrueColor => out.push_str(&format!(
+                    "\x1b[48;2;{};{};{}m\x1b[38;2;{};{};{}m",
+                    bg.r, bg.g, bg.b, fg.r, fg.g, fg.b
+                )),
+                Palette::Ansi256 => {
+                    out.push_str("\x1b[49m");
+                    if Some(bg) != default_background {
+                        out.push_str(&format!("\x1b[48;5;{}m", ansi256(bg)));
+                    }
+                    out.push_str(&format!("\x1b[38;5;{}m", ansi256(fg)));
+                }
+                Palette::Ansi16 => {
+                    out.push_str("\x1b[49m");
+                    if Some(bg) != default_background {
+                        out.push_str(&format!("\x1b[{}m", ansi16(bg, 40)));
+                    }
+                    out.push_str(&format!("\x1b[{}m", ansi16(fg, 30)));
+                }
+                Palette::Plain => {
+                    out.push_str(text);
+                    continue;
+                }
+            }
+            for (flag, code) in [
+                (FontStyle::BOLD, "\x1b[1m"),
+                (FontStyle::ITALIC, "\x1b[3m"),
+                (FontStyle::UNDERLINE, "\x1b[4m"),
+            ] {
+                if style.font_style.contains(flag) {
+                    out.push_str(code);
+                }
+            }
+            out.push_str(text);
+            if !style.font_style.is_empty() {
+                out.push_str("\x1b[22;23;24m");
+            }
+        }
+        out
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> i32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2);
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+/// The closest color of the xterm 256 color palette, from its 6x6x6 cube or its grayscale ramp.
+fn ansi256(color: Color) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |v: u8| {
+        (0..LEVELS.len())
+            .min_by_key(|&i| (LEVELS[i] as i32 - v as i32).abs())
+            .unwrap_or(0)
+    };
+    let rgb = (color.r, color.g, color.b);
+    let (r, g, b) = (level(color.r), level(color.g), level(color.b));
+    let cube = (LEVELS[r], LEVELS[g], LEVELS[b]);
+    let average = (color.r as u32 + color.g as u32 + color.b as u32) / 3;
+    let step = (average.saturating_sub(8) / 10).min(23) as u8;
+    let gray = 8 + 10 * step;
+    if distance(rgb, (gray, gray, gray)) < distance(rgb, cube) {
+        232 + step
+    } else {
+        (16 + 36 * r + 6 * g + b) as u8
+    }
+}
+
+/// The SGR code of the closest of the 16 standard colors, counting from `base`, 30 for foregrounds and 40 for backgrounds.
+fn ansi16(color: Color, base: u8) -> u8 {
+    // The xterm defaults.
+    const COLORS: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    let i = (0..COLORS.len())
+        .min_by_key(|&i| distance(COLORS[i], (color.r, color.g, color.b)))
+        .unwrap_or(0) as u8;
+    if i < 8 {
+        base + i
+    } else {
+        base + 60 + i - 8
+    }
+}
+
+/// A line of carets under the matches of a line, for output without colors.
+pub fn match_markers(line: &str, matches: &[Range<usize>]) -> String {
+    let mut markers = String::new();
+    let mut at = 0;
+    for m in matches {
+        if m.start < at {
+            continue;
+        }
+        // Keep tabs so the carets line up with the text above.
+        markers.extend(
+            line[at..m.start]
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' }),
+        );
+        let width = line[m.clone()].trim_end_matches('\n').chars().count();
+        markers.extend(std::iter::repeat_n('^', width.max(1)));
+        at = m.end;
+    }
+    markers.push('\n');
+    markers
+}
+
+/// Where rgrok looks for its configuration, following the XDG convention.
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|config| config.join("rgrok"))
+}
+
+lazy_static! {
+    /// Checked once, so a missing rustfmt doesn't cost a failed spawn per item.
+    static ref HAS_RUSTFMT: bool = {
+        let found = Command::new("rustfmt")
+            .arg("--version")
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false);
+        if !found {
+            eprintln!("rustfmt was not found, showing items as written");
+        }
+        found
+    };
+}
+
+impl RenderOptions {
+    /// Add the themes from the theme directory to `ts`, and the theme file given with --theme if any.
+    pub fn load_themes(&self, ts: &mut ThemeSet) -> Result<()> {
+        let theme_dir = match &self.theme_dir {
+            Some(dir) => Some(dir.clone()),
+            None => config_dir()
+                .map(|config| config.join("themes"))
+                .filter(|dir| dir.is_dir()),
+        };
+        if let Some(dir) = theme_dir {
+            ts.add_from_folder(&dir)
+                .map_err(|e| eyre::eyre!("Failed to load themes from {}: {}", dir.display(), e))?;
+        }
+        let path = Path::new(&self.theme);
+        if !ts.themes.contains_key(&self.theme) && path.is_file() {
+            let theme = ThemeSet::get_theme(path)
+                .map_err(|e| eyre::eyre!("Failed to load theme {}: {}", path.display(), e))?;
+            ts.themes.insert(self.theme.clone(), theme);
+        }
+        Ok(())
+    }
+
+    /// The theme chosen with --theme.
+    pub fn theme<'t>(&self, ts: &'t ThemeSet) -> Result<&'t Theme> {
+        ts.themes.get(&self.theme).ok_or_else(|| {
+            let names: Vec<&str> = ts.themes.keys().map(String::as_str).collect();
+            eyre::eyre!(
+                "Invalid theme {:?}, expected one of: {}",
+                self.theme,
+                names.join(", ")
+            )
+        })
+    }
+
+    /// The palette to render with, taking --color, `NO_COLOR` and the terminal into account.
+    pub fn palette(&self) -> Palette {
+        match self.color {
+            ColorChoice::Never => Palette::Plain,
+            ColorChoice::Always => Palette::detect(),
+            ColorChoice::Auto => {
+                let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                let dumb = std::env::var("TERM").is_ok_and(|term| term == "dumb");
+                if no_color || dumb || !std::io::stdout().is_terminal() {
+                    Palette::Plain
+                } else {
+                    Palette::detect()
+                }
+            }
+        }
+    }
+
+    /// The style applied on top of the syntax highlighting for matches.
+    pub fn match_style(&self) -> StyleModifier {
+        StyleModifier {
+            foreground: Some(self.match_fg.0),
+            background: self.match_bg.map(|color| color.0),
+            font_style: Some(
+                self.match_style
+                    .iter()
+                    .fold(FontStyle::empty(), |style, flag| style | flag.0),
+            ),
+        }
+    }
+
+    /// The style of the line numbers, from the theme's gutter colors.
+    pub fn gutter_style(&self, theme: &Theme) -> Style {
+        let settings = &theme.settings;
+        Style {
+            foreground: settings
+                .gutter_foreground
+                .or(settings.foreground)
+                .unwrap_or(Color::WHITE),
+            background: settings
+                .gutter
+                .or(settings.background)
+                .unwrap_or(Color::BLACK),
+            font_style: FontStyle::empty(),
+        }
+    }
+
+    /// The source to display for an item, along with the byte ranges of its matches within it.
+    pub fn source(&self, item: &ItemMatch) -> Source {
+        if let (true, Some(signature)) = (self.signatures, &item.signature) {
+            return signature_source(item, signature);
+        }
+        if self.format && item.item_type != ItemType::Line && *HAS_RUSTFMT {
+            if let Some((text, matches)) = format_item(item) {
+                return Source {
+                    item_range: 0..text.len(),
+                    text,
+                    matches,
+                    first_line: None,
+                    elided_matches: 0,
+                };
+            }
+        }
+        Source {
+            text: item.source.clone(),
+            matches: item.source_ranges(),
+            first_line: Some(item.line_range.0),
+            item_range: item.byte_range.0 - item.source_start
+                ..item.byte_range.1 - item.source_start,
+            elided_matches: 0,
+        }
+    }
+}
+
+/// The text shown for an item.
+pub struct Source {
+    pub text: String,
+    /// Byte ranges of the matches in `text`, in the order of the item's matches.
+    pub matches: Vec<Range<usize>>,
+    /// Line of the file `text` starts at, or `None` once reformatted, when its lines no longer match the file's.
+    pub first_line: Option<usize>,
+    /// Byte range of the item in `text`, which may start partway through its first line.
+    pub item_range: Range<usize>,
+    /// Matches of the item that aren't in `text`, when only its signature is shown.
+    pub elided_matches: usize,
+}
+
+/// Show an item as its signature, marking the matches before its body and counting those in it.
+fn signature_source(item: &ItemMatch, signature: &str) -> Source {
+    let (head, body): (Vec<&MatchSpan>, Vec<&MatchSpan>) = item
+        .matches
+        .iter()
+        .partition(|m| item.body_start.is_none_or(|start| m.byte_range.1 <= start));
+    let mut matches = Vec::new();
+    let mut at = 0;
+    for m in head {
+        // The signature is rebuilt from tokens, so matches spanning several tokens may not be found in it,
+        // nor are those in attributes.
+        if let Some(start) = signature[at..].find(&m.text).map(|i| at + i) {
+            matches.push(start..start + m.text.len());
+            at = start + m.text.len();
+        }
+    }
+    let text = format!("{}\n", signature);
+    Source {
+        elided_matches: body.len(),
+        matches,
+        first_line: Some(item.name_line.unwrap_or(item.line_range.0)),
+        item_range: 0..text.len(),
+        text,
+    }
+}
+
+/// Format the item with rustfmt, moving its matches onto the formatted source.
+/// Gives up if rustfmt fails or changes the item beyond its layout.
+fn format_item(item: &ItemMatch) -> Option<(String, Vec<Range<usize>>)> {
+    let start = item.byte_range.0.checked_sub(item.source_start)?;
+    let end = item.byte_range.1.checked_sub(item.source_start)?;
+    let text = item.source.get(start..end)?;
+    // Matches outside the item itself can't be placed in its formatted source, show it as written.
+    let ranges = item
+        .source_ranges()
+        .into_iter()
+        .map(|r| (r.start >= start && r.end <= end).then(|| r.start - start..r.end - start))
+        .collect::<Option<Vec<Range<usize>>>>()?;
+
+    let (open, close) = wrapper(item.item_type);
+    let formatted = rustfmt(format!("{}{}\n{}", open, text, close)).ok()?;
+    let formatted = if open.is_empty() {
+        formatted
+    } else {
+        let inner = formatted.strip_prefix(open)?.strip_suffix(close)?;
+        inner
+            .split_inclusive('\n')
+            .map(|line| line.strip_prefix("    ").unwrap_or(line))
+            .collect()
+    };
+
+    let ranges = remap(text, &formatted, &ranges)?;
+    Some((formatted, ranges))
+}
+
+/// What an item needs around it to parse on its own, as members of impls, traits and foreign mods don't.
+pub fn wrapper(item_type: ItemType) -> (&'static str, &'static str) {
+    match item_type {
+        ItemType::ImplConst
+        | ItemType::ImplMethod
+        | ItemType::ImplType
+        | ItemType::ImplMacro
+        | ItemType::ImplVerbatim => ("impl __ {\n", "}\n"),
+        ItemType::TraitConst
+        | ItemType::TraitMethod
+        | ItemType::TraitType
+        | ItemType::TraitMacro
+        | ItemType::TraitVerbatim => ("trait __ {\n", "}\n"),
+        ItemType::ForeignFn
+        | ItemType::ForeignStatic
+        | ItemType::ForeignType
+        | ItemType::ForeignMacro
+        | ItemType::ForeignVerbatim => ("extern \"C\" {\n", "}\n"),
+        _ => ("", ""),
+    }
+}
+
+/// Punctuation that rustfmt adds or removes when laying out code, such as trailing commas.
+fn is_layout(c: char) -> bool {
+    matches!(c, ',' | ';' | '{' | '}')
+}
+
+/// Map byte ranges of `original` onto `formatted` by lining up the non-whitespace chars of both.
+/// Returns `None` if a range can't be mapped.
+fn remap(original: &str, formatted: &str, ranges: &[Range<usize>]) -> Option<Vec<Range<usize>>> {
+    let mut map = vec![None; original.len()];
+    let mut a = original
+        .char_indices()
+        .filter(|(_, c)| !c.is_whitespace())
+        .peekable();
+    let mut b = formatted
+        .char_indices()
+        .filter(|(_, c)| !c.is_whitespace())
+        .peekable();
+    while let (Some(&(i, c)), Some(&(j, d))) = (a.peek(), b.peek()) {
+        if c == d {
+            map[i] = Some(j);
+            a.next();
+            b.next();
+        } else if is_layout(d) {
+            b.next();
+        } else if is_layout(c) {
+            a.next();
+        } else {
+            break;
+        }
+    }
+
+    ranges
+        .iter()
+        .map(|range| {
+            let text = &original[range.clone()];
+            let (first, _) = text.char_indices().find(|(_, c)| !c.is_whitespace())?;
+            let (last, c) = text.char_indices().rfind(|(_, c)| !c.is_whitespace())?;
+            Some(map[range.start + first]?..map[range.start + last]? + c.len_utf8())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::ItemFilter;
+    use crate::search::{line_regex, search_file};
+    use crate::util::TempDir;
+
+    /// The signature shown for each item matching `pattern`, with its marked matches and body count.
+    fn signatures(source: &str, pattern: &str) -> Vec<(String, Vec<String>, usize)> {
+        let dir = TempDir::new("render-signatures");
+        dir.write("lib.rs", source);
+        let re = line_regex(pattern).unwrap();
+        let items = search_file(
+            &dir.parsed("lib.rs"),
+            Some(&re),
+            &ItemFilter::default(),
+            &[],
+        )
+        .unwrap();
+        let options = RenderOptions {
+            signatures: true,
+            ..RenderOptions::default()
+        };
+        items
+            .iter()
+            .map(|item| {
+                let source = options.source(item);
+                let marked = source
+                    .matches
+                    .iter()
+                    .map(|range| format!("{}@{}", &source.text[range.clone()], range.start))
+                    .collect();
+                (
+                    source.text.trim_end().to_string(),
+                    marked,
+                    source.elided_matches,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn signature_matches_are_split_by_offset() {
+        let source = "fn retry() { // retry later\n}\n\nconst RETRY: u8 = retry();\n";
+        assert_eq!(
+            signatures(source, "retry later"),
+            [("fn retry()".to_string(), vec![], 1)]
+        );
+        assert_eq!(
+            signatures(source, "(?i)retry"),
+            [
+                ("fn retry()".to_string(), vec!["retry@3".to_string()], 1),
+                (
+                    "const RETRY: u8".to_string(),
+                    vec!["RETRY@6".to_string()],
+                    1
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use crate::matches::ItemMatch;
+use crate::rustfmt;
+use crate::util::print_header_info;
+use crate::Compositor;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use clap::Clap;
+use regex::Regex;
+
+/// Rewrites the matches inside the reported items, like a sed scoped to items.
+#[derive(Clap, Clone, Default)]
+pub struct Rewrite {
+    /// Replace each match with this template, which can refer to capture groups as `$1` or `${name}`.
+    #[clap(long)]
+    pub replace: Option<String>,
+    /// Show a diff of the replacements in each item without editing any file, the default.
+    #[clap(long, requires = "replace", conflicts_with = "write")]
+    pub dry_run: bool,
+    /// Edit the files in place.
+    #[clap(long, requires = "replace")]
+    pub write: bool,
+    /// Run the edited files through rustfmt.
+    #[clap(long, requires = "write")]
+    pub rustfmt: bool,
+}
+
+/// A replacement to make in a file.
+struct Edit {
+    range: Range<usize>,
+    /// The matched text, to check the file hasn't changed since it was searched.
+    expected: String,
+    replacement: String,
+}
+
+/// Writes the replacements for each item as a diff, or collects them to edit the files once finished.
+pub struct ReplacePrinter<W: Write> {
+    output: W,
+    regex: Regex,
+    template: String,
+    write: bool,
+    rustfmt: bool,
+    /// Whether to color the diffs.
+    colored: bool,
+    edits: BTreeMap<PathBuf, Vec<Edit>>,
+}
+
+impl<W: Write> ReplacePrinter<W> {
+    pub fn new(
+        output: W,
+        regex: Regex,
+        template: String,
+        rewrite: &Rewrite,
+        colored: bool,
+    ) -> Self {
+        Self {
+            output,
+            regex,
+            template,
+            write: rewrite.write,
+            rustfmt: rewrite.rustfmt,
+            colored,
+            edits: BTreeMap::new(),
+        }
+    }
+
+    /// The replacement for each match of the item, with its capture groups expanded.
+    fn replacements(&self, item: &ItemMatch) -> Vec<String> {
+        // The source starts at a line boundary, so anchors match as they did in the whole file.
+        let captures: HashMap<usize, regex::Captures> = self
+            .regex
+            .captures_iter(&item.source)
+            .filter_map(|c| Some((c.get(0)?.start(), c)))
+            .collect();
+        item.source_ranges()
+            .into_iter()
+            .zip(&item.matches)
+            .map(|(range, m)| match captures.get(&range.start) {
+                Some(c) => {
+                    let mut replacement = String::new();
+                    c.expand(&self.template, &mut replacement);
+                    replacement
+                }
+                None => self
+                    .regex
+                    .replace(&m.text, self.template.as_str())
+                    .into_owned(),
+            })
+            .collect()
+    }
+
+    /// Write the lines changed by the replacements, before and after.
+    fn write_diff(&mut self, item: &ItemMatch, replacements: &[String]) -> std::io::Result<()> {
+        let source = &item.source;
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset) - 1;
+        let ranges = item.source_ranges();
+
+        writeln!(self.output)?;
+        print_header_info(&mut self.output, item)?;
+        let mut i = 0;
+        while i < ranges.len() {
+            // Gather the matches sharing lines into a single hunk.
+            let first = line_of(ranges[i].start);
+            let mut last = line_of(ranges[i].end.max(ranges[i].start + 1) - 1);
+            let mut j = i + 1;
+            while j < ranges.len() && line_of(ranges[j].start) <= last {
+                last = last.max(line_of(ranges[j].end.max(ranges[j].start + 1) - 1));
+                j += 1;
+            }
+            let start = line_starts[first];
+            let end = line_starts.get(last + 1).copied().unwrap_or(source.len());
+
+            let mut replaced = String::new();
+            let mut at = start;
+            for (range, replacement) in ranges[i..j].iter().zip(&replacements[i..j]) {
+                replaced.push_str(&source[at..range.start]);
+                replaced.push_str(replacement);
+                at = range.end;
+            }
+            replaced.push_str(&source[at..end]);
+
+            let line = item.line_range.0 + first;
+            let (red, green, reset) = if self.colored {
+                ("\x1b[31m", "\x1b[32m", "\x1b[0m")
+            } else {
+                ("", "", "")
+            };
+            for (n, text) in source[start..end].lines().enumerate() {
+                writeln!(self.output, "{}{:>5} -{}{}", red, line + n, text, reset)?;
+            }
+            for (n, text) in replaced.lines().enumerate() {
+                writeln!(self.output, "{}{:>5} +{}{}", green, line + n, text, reset)?;
+            }
+            i = j;
+        }
+        Ok(())
+    }
+
+    /// Apply the collected replacements to each file.
+    fn write_files(&mut self) -> color_eyre::Result<()> {
+        for (path, edits) in &mut self.edits {
+            let contents = std::fs::read_to_string(path)?;
+            let mut contents = apply_edits(contents, edits).ok_or_else(|| {
+                color_eyre::eyre::eyre!(
+                    "{} changed since it was searched, not editing it",
+                    path.display()
+                )
+            })?;
+            if self.rustfmt {
+                contents = rustfmt(contents)?;
+            }
+            std::fs::write(path, contents)?;
+            writeln!(
+                self.output,
+                "{}: {} replacement(s)",
+                path.display(),
+                edits.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Make the edits to the contents of a file, from last to first so earlier ranges stay valid.
+/// Returns `None` if any edit doesn't find the text it expects.
+fn apply_edits(mut contents: String, edits: &mut [Edit]) -> Option<String> {
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.range.start));
+    for edit in edits.iter() {
+        if contents.get(edit.range.clone()) != Some(edit.expected.as_str()) {
+            return None;
+        }
+        contents.replace_range(edit.range.clone(), &edit.replacement);
+    }
+    Some(contents)
+}
+
+impl<W: Write> Write for ReplacePrinter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<W: Write> Compositor for ReplacePrinter<W> {
+    type Context = ItemMatch;
+
+    fn renders(&self) -> bool {
+        false
+    }
+
+    fn write_with(
+        &mut self,
+        _: std::fmt::Arguments,
+        item: Self::Context,
+    ) -> std::result::Result<(), std::io::Error> {
+        let replacements = self.replacements(&item);
+        if !self.write {
+            return self.write_diff(&item, &replacements);
+        }
+        let edits = self.edits.entry(item.path).or_default();
+        for (m, replacement) in item.matches.into_iter().zip(replacements) {
+            edits.push(Edit {
+                range: m.byte_range.0..m.byte_range.1,
+                expected: m.text,
+                replacement,
+            });
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::result::Result<(), std::io::Error> {
+        self.write_files()
+            .map_err(|e| std::io::Error::other(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+    use crate::{rgrok_dir, Args};
+
+    use clap::Clap;
+    use syntect::highlighting::ThemeSet;
+    use syntect::parsing::SyntaxSet;
+
+    fn edit(range: Range<usize>, expected: &str, replacement: &str) -> Edit {
+        Edit {
+            range,
+            expected: expected.to_string(),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn edits_apply_from_last_to_first() {
+        let mut edits = vec![
+            edit(3..6, "one", "first"),
+            edit(16..19, "two", "2"),
+            edit(7..10, "one", "1"),
+        ];
+        assert_eq!(
+            apply_edits("fn one(one: u8, two: u8)".to_string(), &mut edits).as_deref(),
+            Some("fn first(1: u8, 2: u8)")
+        );
+    }
+
+    #[test]
+    fn edits_check_the_expected_text() {
+        let mut edits = vec![edit(3..6, "one", "1"), edit(7..10, "two", "2")];
+        assert_eq!(apply_edits("fn one(one)".to_string(), &mut edits), None);
+        let mut edits = vec![edit(8..20, "one", "1")];
+        assert_eq!(apply_edits("fn one(one)".to_string(), &mut edits), None);
+    }
+
+    #[test]
+    fn write_edits_files_in_place() {
+        let dir = TempDir::new("replace");
+        let path = dir.write(
+            "lib.rs",
+            "fn old_name(old_value: u8) {}\n\n// old_name outside any item\nstruct Old;\n",
+        );
+
+        let args = Args::parse_from([
+            "rgrok",
+            "--path",
+            dir.0.to_str().unwrap(),
+            "old_(\\w+)",
+            "--replace",
+            "new_$1",
+            "--write",
+        ]);
+        let ps = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        rgrok_dir(args, &ps, &ts).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "fn new_name(new_value: u8) {}\n\n// old_name outside any item\nstruct Old;\n"
+        );
+    }
+}
@@ -0,0 +1,519 @@
+use crate::filter::ItemFilter;
+use crate::index::ItemIndex;
+use crate::is_rust_file;
+use crate::matches::{ItemMatch, MatchSpan};
+use crate::tokens::{TokenClass, TokenMap};
+use crate::util::{module_path, parse_file, ItemType, ParsedFile};
+use crate::visit::{collect_items, item_at_offset, item_scope, ItemSpan, Node};
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use color_eyre::{eyre, Report, Result};
+use ignore::{DirEntry, Walk};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Builds a search over a directory tree, yielding every item that contains a match.
+///
+/// ```no_run
+/// # fn main() -> color_eyre::Result<()> {
+/// let searcher = rgrok::search::Searcher::new()
+///     .regex(regex::Regex::new("unwrap")?)
+///     .path("src");
+/// for item in searcher.search() {
+///     let item = item?;
+///     println!("{} {:?} {:?}", item.path.display(), item.item_type, item.ident);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct Searcher {
+    path: PathBuf,
+    regex: Option<Regex>,
+    filter: ItemFilter,
+    within: Vec<TokenClass>,
+    fallback: bool,
+    sort: SortBy,
+    index: Option<Arc<ItemIndex>>,
+}
+
+/// The order to report files in. Items within a file are always in source order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortBy {
+    /// Whichever order the walk finds them in, the fastest.
+    #[default]
+    None,
+    Path,
+    /// Oldest first.
+    Modified,
+}
+
+impl FromStr for SortBy {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(SortBy::None),
+            "path" => Ok(SortBy::Path),
+            "modified" => Ok(SortBy::Modified),
+            _ => Err(eyre::eyre!(
+                "Invalid sort {:?}, expected one of: none, path, modified",
+                s
+            )),
+        }
+    }
+}
+
+impl SortBy {
+    /// Sort the files found by a walk. Errors, which have no entry, go first.
+    pub fn sort<T>(&self, files: &mut [T], entry: impl Fn(&T) -> Option<&DirEntry>) {
+        if *self == SortBy::None {
+            return;
+        }
+        files.sort_by_cached_key(|file| {
+            entry(file).map(|entry| {
+                let modified = match self {
+                    SortBy::Modified => entry.metadata().ok().and_then(|m| m.modified().ok()),
+                    _ => None,
+                };
+                (modified, entry.path().to_path_buf())
+            })
+        });
+    }
+}
+
+impl Searcher {
+    pub fn new() -> Self {
+        Self {
+            path: ".".into(),
+            ..Self::default()
+        }
+    }
+
+    /// Without a regex, every item accepted by the filter is reported, with no matches.
+    /// `^` and `$` match at the start and end of each line, see [`line_regex`].
+    pub fn regex(mut self, regex: impl Into<Option<Regex>>) -> Self {
+        self.regex = regex.into().map(|re| line_regex(re.as_str()).unwrap_or(re));
+        self
+    }
+
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    pub fn filter(mut self, filter: ItemFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Only keep matches that fall within these classes of tokens. Empty matches anywhere.
+    pub fn within(mut self, within: Vec<TokenClass>) -> Self {
+        self.within = within;
+        self
+    }
+
+    /// Search files that fail to parse line by line, instead of skipping them.
+    pub fn fallback(mut self, fallback: bool) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Any order other than [`SortBy::None`] walks the whole tree before searching it.
+    pub fn sort(mut self, sort: SortBy) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Take the items of unchanged files from the index instead of parsing them.
+    pub fn index(mut self, index: impl Into<Option<ItemIndex>>) -> Self {
+        self.index = index.into().map(Arc::new);
+        self
+    }
+
+    /// Search a single file.
+    /// If it fails to parse, the error is returned alongside the fallback matches, if enabled.
+    pub fn search_file(&self, file: &ParsedFile) -> (Vec<ItemMatch>, Option<ParseError>) {
+        if let Some(items) = self.index.as_ref().and_then(|index| index.items(file)) {
+            let matches =
+                search_items(file, items, self.regex.as_ref(), &self.filter, &self.within);
+            return (matches, None);
+        }
+        match search_file(file, self.regex.as_ref(), &self.filter, &self.within) {
+            Ok(items) => (items, None),
+            Err(e) if self.fallback => (
+                self.regex
+                    .as_ref()
+                    .map(|re| search_lines(file, re, &self.filter, &self.within))
+                    .unwrap_or_default(),
+                Some(e),
+            ),
+            Err(e) => (Vec::new(), Some(e)),
+        }
+    }
+
+    /// Walk the tree lazily, searching each rust file as it is reached.
+    pub fn search(&self) -> Matches<'_> {
+        let walk = Walk::new(&self.path);
+        let walk: Box<dyn Iterator<Item = Result<DirEntry, ignore::Error>>> = match self.sort {
+            SortBy::None => Box::new(walk),
+            sort => {
+                let mut entries: Vec<_> = walk.collect();
+                sort.sort(&mut entries, |entry| entry.as_ref().ok());
+                Box::new(entries.into_iter())
+            }
+        };
+        Matches {
+            searcher: self,
+            walk,
+            pending: Vec::new().into_iter(),
+        }
+    }
+}
+
+/// Iterator over the items matched by a [`Searcher`], in walk order.
+pub struct Matches<'a> {
+    searcher: &'a Searcher,
+    walk: Box<dyn Iterator<Item = Result<DirEntry, ignore::Error>>>,
+    pending: std::vec::IntoIter<ItemMatch>,
+}
+
+impl Iterator for Matches<'_> {
+    type Item = Result<ItemMatch>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.next() {
+                return Some(Ok(item));
+            }
+            match self.walk.next()? {
+                Ok(dir_entry) if is_rust_file(&dir_entry) => match parse_file(dir_entry) {
+                    Ok(file) => {
+                        let (items, error) = self.searcher.search_file(&file);
+                        self.pending = items.into_iter();
+                        if let Some(error) = error {
+                            return Some(Err(error.into()));
+                        }
+                    }
+                    Err(e) => return Some(Err(e)),
+                },
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref LINE_REGEX: regex::Regex = regex::RegexBuilder::new("(.*\r?\n?)")
+        .multi_line(true)
+        .build()
+        .unwrap();
+}
+
+/// Compile a pattern so that `^` and `$` match at the start and end of lines, as when searching
+/// line by line, rather than of the whole file that the regex runs over.
+pub fn line_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    regex::RegexBuilder::new(pattern).multi_line(true).build()
+}
+
+/// A file that `syn` was unable to parse.
+#[derive(Debug)]
+pub struct ParseError {
+    pub path: PathBuf,
+    pub error: syn::Error,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let start = self.error.span().start();
+        write!(
+            fmt,
+            "{}:{}:{}: failed to parse: {}",
+            self.path.display(),
+            start.line,
+            start.column + 1,
+            self.error
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Find the items of a file containing a match, in source order.
+/// Without a regex, every item accepted by the filter is returned.
+pub fn search_file(
+    file: &ParsedFile,
+    re: Option<&Regex>,
+    filter: &ItemFilter,
+    within: &[TokenClass],
+) -> Result<Vec<ItemMatch>, ParseError> {
+    let syn_file = syn::parse_file(&file.contents).map_err(|error| ParseError {
+        path: file.dir_entry.path().to_path_buf(),
+        error,
+    })?;
+    let items = collect_items(&syn_file.items);
+    Ok(search_items(file, &items, re, filter, within))
+}
+
+/// Search a file whose items are already known, either freshly parsed or read from the index.
+pub fn search_items<N: Node>(
+    file: &ParsedFile,
+    items: &[ItemSpan<N>],
+    re: Option<&Regex>,
+    filter: &ItemFilter,
+    within: &[TokenClass],
+) -> Vec<ItemMatch> {
+    let byte_spans = line_spans(file);
+    let byte_ranges: Vec<Range<usize>> = items
+        .iter()
+        .map(|item| {
+            let (start, end) = item.line_range;
+            let (start_column, end_column) = item.column_range;
+            offset_of(&file.contents, &byte_spans, start, start_column)
+                ..offset_of(&file.contents, &byte_spans, end, end_column)
+        })
+        .collect();
+
+    // Group the matches by the item they were found in.
+    let mut grouped: BTreeMap<usize, Vec<regex::Match>> = BTreeMap::new();
+    match re {
+        Some(re) => {
+            for m in scoped_matches(file, &byte_spans, re, within) {
+                if let Some(i) = filter.select(items, item_at_offset(&byte_ranges, m.start())) {
+                    grouped.entry(i).or_default().push(m);
+                }
+            }
+        }
+        None => {
+            for i in 0..items.len() {
+                if filter.accepts(items, i) {
+                    grouped.insert(i, Vec::new());
+                }
+            }
+        }
+    }
+    if grouped.is_empty() {
+        return Vec::new();
+    }
+
+    let path = file.dir_entry.path();
+    let file_module = module_path(path);
+    grouped
+        .into_iter()
+        .map(|(i, matches)| {
+            let item = &items[i];
+            let (modules, scope) = item_scope(items, i);
+            let (start, end) = item.line_range;
+            ItemMatch {
+                path: path.to_path_buf(),
+                item_type: item.item_type,
+                ident: item.ident.clone(),
+                signature: Some(item.node.signature()),
+                body_start: item
+                    .node
+                    .body_start()
+                    .map(|(line, column)| offset_of(&file.contents, &byte_spans, line, column)),
+                module_path: file_module.iter().chain(&modules).cloned().collect(),
+                scope,
+                line_range: (start, end),
+                byte_range: (byte_ranges[i].start, byte_ranges[i].end),
+                name_line: item.name_line,
+                matches: matches
+                    .into_iter()
+                    .map(|m| match_span(&byte_spans, m))
+                    .collect(),
+                // Lines are 1-indexed, byte_spans is 0-indexed.
+                source: file.contents[byte_spans[start - 1]..byte_spans[end]].to_string(),
+                source_start: byte_spans[start - 1],
+            }
+        })
+        .collect()
+}
+
+/// Plain line based search, for files that can't be broken into items.
+/// Each matching line is reported as an item of type [`ItemType::Line`].
+/// Token classes are only respected if the file can still be lexed.
+pub fn search_lines(
+    file: &ParsedFile,
+    re: &Regex,
+    filter: &ItemFilter,
+    within: &[TokenClass],
+) -> Vec<ItemMatch> {
+    if !filter.accepts_kind(ItemType::Line) {
+        return Vec::new();
+    }
+    let byte_spans = line_spans(file);
+    let mut grouped: BTreeMap<usize, Vec<MatchSpan>> = BTreeMap::new();
+    for m in scoped_matches(file, &byte_spans, re, within) {
+        // An empty match at the very end, e.g. of `$`, comes after the last line if there's a final newline.
+        if m.start() == file.contents.len()
+            && (file.contents.is_empty() || file.contents.ends_with('\n'))
+        {
+            continue;
+        }
+        let m = match_span(&byte_spans, m);
+        grouped.entry(m.line).or_default().push(m);
+    }
+    if grouped.is_empty() {
+        return Vec::new();
+    }
+    let path = file.dir_entry.path();
+    let file_module = module_path(path);
+    grouped
+        .into_iter()
+        .map(|(line, matches)| {
+            let (start, end) = (byte_spans[line - 1], byte_spans[line]);
+            ItemMatch {
+                path: path.to_path_buf(),
+                item_type: ItemType::Line,
+                ident: None,
+                signature: None,
+                body_start: None,
+                module_path: file_module.clone(),
+                scope: Vec::new(),
+                line_range: (line, line),
+                byte_range: (start, end),
+                name_line: None,
+                matches,
+                source: file.contents[start..end].to_string(),
+                source_start: start,
+            }
+        })
+        .collect()
+}
+
+/// Matches of the regex in the file, restricted to the given token classes.
+fn scoped_matches<'t>(
+    file: &'t ParsedFile,
+    byte_spans: &[usize],
+    re: &Regex,
+    within: &[TokenClass],
+) -> Vec<regex::Match<'t>> {
+    let token_map = if within.is_empty() {
+        None
+    } else {
+        TokenMap::new(&file.contents, byte_spans)
+    };
+    re.find_iter(&file.contents)
+        .filter(|m| match &token_map {
+            Some(token_map) => token_map.contains(within, m.range()),
+            None => true,
+        })
+        .collect()
+}
+
+fn match_span(byte_spans: &[usize], m: regex::Match) -> MatchSpan {
+    // The end of a file without a final newline is still on its last line.
+    let line = line_of(byte_spans, m.start()).min(byte_spans.len() - 1);
+    MatchSpan {
+        line,
+        column: m.start() - byte_spans[line - 1] + 1,
+        byte_range: (m.start(), m.end()),
+        text: m.as_str().to_string(),
+    }
+}
+
+/// Indexes for the starting byte offset for a given line.
+/// byte_spans[i]..byte_spans[i+1] = byte range for a line in a file.
+pub fn line_spans(file: &ParsedFile) -> Vec<usize> {
+    let mut byte_spans = vec![0usize];
+    byte_spans.extend(LINE_REGEX.find_iter(&file.contents).scan(0, |acc, l| {
+        *acc += l.as_str().len();
+        Some(*acc)
+    }));
+    assert_eq!(
+        byte_spans.last().unwrap(),
+        &file.contents.len(),
+        "{}",
+        file.dir_entry.path().display()
+    );
+    byte_spans
+}
+
+/// Find the 1-indexed line containing the byte `offset`, given the line table built by [`line_spans`].
+pub fn line_of(byte_spans: &[usize], offset: usize) -> usize {
+    match byte_spans.binary_search(&offset) {
+        // The offset is the first byte of a line.
+        Ok(i) => i + 1,
+        // The offset is somewhere in the line before.
+        Err(i) => i,
+    }
+}
+
+/// Convert a 1-indexed line and a char column, as reported by `proc_macro2`, to a byte offset.
+pub fn offset_of(contents: &str, byte_spans: &[usize], line: usize, column: usize) -> usize {
+    let start = byte_spans[line - 1];
+    contents[start..]
+        .char_indices()
+        .nth(column)
+        .map(|(i, _)| start + i)
+        .unwrap_or_else(|| contents.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    fn labels(searcher: &Searcher) -> Vec<String> {
+        searcher
+            .search()
+            .map(|item| item.unwrap().scope.join(" > "))
+            .collect()
+    }
+
+    #[test]
+    fn anchors_match_at_lines() {
+        let dir = TempDir::new("search-anchors");
+        dir.write(
+            "lib.rs",
+            "struct A;\n\nimpl A {\n    fn a() -> Result<()> {\n        Ok(())\n    }\n}\n\n\
+             impl B for A {}\n",
+        );
+        let searcher = |pattern: &str| {
+            Searcher::new()
+                .path(&dir.0)
+                .regex(Regex::new(pattern).unwrap())
+        };
+        assert_eq!(labels(&searcher("^impl")), ["impl A", "impl B for A"]);
+        assert_eq!(labels(&searcher(r"Ok\(\(\)\)$")), ["impl A > fn a"]);
+        assert!(labels(&searcher(r"\Aimpl")).is_empty());
+    }
+
+    #[test]
+    fn empty_matches_at_the_end_of_a_broken_file() {
+        let dir = TempDir::new("search-eof");
+        dir.write("lib.rs", "fn broken( {\n");
+        let file = dir.parsed("lib.rs");
+        let lines = |pattern: &str| -> Vec<usize> {
+            let re = line_regex(pattern).unwrap();
+            search_lines(&file, &re, &ItemFilter::default(), &[])
+                .iter()
+                .map(|item| item.line_range.0)
+                .collect()
+        };
+        assert_eq!(lines("$"), [1]);
+        assert_eq!(lines("z*"), [1]);
+        assert_eq!(lines("broken"), [1]);
+
+        dir.write("lib.rs", "fn broken( {\n    a");
+        let file = dir.parsed("lib.rs");
+        let ends = search_lines(
+            &file,
+            &line_regex("$").unwrap(),
+            &ItemFilter::default(),
+            &[],
+        );
+        let columns: Vec<(usize, usize)> = ends
+            .iter()
+            .flat_map(|item| &item.matches)
+            .map(|m| (m.line, m.column))
+            .collect();
+        assert_eq!(columns, [(1, 13), (2, 6)]);
+    }
+}
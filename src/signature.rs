@@ -0,0 +1,205 @@
+use crate::util::tokens_to_string;
+use crate::visit::ItemNode;
+
+use proc_macro2::LineColumn;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{Fields, ForeignItem, ImplItem, Item, MacroDelimiter, TraitItem};
+
+/// The declaration of an item without its attributes or body,
+/// e.g. `pub fn connect(addr: &str) -> Result<Client>` or `impl<T> Display for Wrapper<T>`.
+pub fn signature(node: ItemNode) -> String {
+    let tokens = match node {
+        ItemNode::Item(item) => match item {
+            Item::Const(i) => {
+                let (vis, ident, ty) = (&i.vis, &i.ident, &i.ty);
+                quote!(#vis const #ident: #ty)
+            }
+            Item::Enum(i) => {
+                let (vis, ident, generics) = (&i.vis, &i.ident, &i.generics);
+                quote!(#vis enum #ident #generics)
+            }
+            Item::ExternCrate(i) => {
+                let (vis, ident) = (&i.vis, &i.ident);
+                match &i.rename {
+                    Some((_, rename)) => quote!(#vis extern crate #ident as #rename),
+                    None => quote!(#vis extern crate #ident),
+                }
+            }
+            Item::Fn(i) => {
+                let (vis, sig) = (&i.vis, &i.sig);
+                quote!(#vis #sig)
+            }
+            Item::ForeignMod(i) => {
+                let abi = &i.abi;
+                quote!(#abi)
+            }
+            Item::Impl(i) => {
+                let (unsafety, generics, self_ty) = (&i.unsafety, &i.generics, &i.self_ty);
+                match &i.trait_ {
+                    Some((bang, path, _)) => {
+                        quote!(#unsafety impl #generics #bang #path for #self_ty)
+                    }
+                    None => quote!(#unsafety impl #generics #self_ty),
+                }
+            }
+            Item::Macro(i) => {
+                let path = tokens_to_string(&i.mac.path);
+                return match &i.ident {
+                    Some(ident) => format!("{}! {}", path, ident),
+                    None => format!("{}!", path),
+                };
+            }
+            Item::Macro2(i) => {
+                let (vis, ident) = (&i.vis, &i.ident);
+                quote!(#vis macro #ident)
+            }
+            Item::Mod(i) => {
+                let (vis, ident) = (&i.vis, &i.ident);
+                quote!(#vis mod #ident)
+            }
+            Item::Static(i) => {
+                let (vis, mutability, ident, ty) = (&i.vis, &i.mutability, &i.ident, &i.ty);
+                quote!(#vis static #mutability #ident: #ty)
+            }
+            Item::Struct(i) => {
+                let (vis, ident, generics) = (&i.vis, &i.ident, &i.generics);
+                quote!(#vis struct #ident #generics)
+            }
+            Item::Trait(i) => {
+                let (vis, unsafety, auto, ident, generics) =
+                    (&i.vis, &i.unsafety, &i.auto_token, &i.ident, &i.generics);
+                let (colon, supertraits) = (&i.colon_token, &i.supertraits);
+                quote!(#vis #unsafety #auto trait #ident #generics #colon #supertraits)
+            }
+            Item::TraitAlias(i) => {
+                let (vis, ident, generics, bounds) = (&i.vis, &i.ident, &i.generics, &i.bounds);
+                quote!(#vis trait #ident #generics = #bounds)
+            }
+            Item::Type(i) => {
+                let (vis, ident, generics, ty) = (&i.vis, &i.ident, &i.generics, &i.ty);
+                quote!(#vis type #ident #generics = #ty)
+            }
+            Item::Union(i) => {
+                let (vis, ident, generics) = (&i.vis, &i.ident, &i.generics);
+                quote!(#vis union #ident #generics)
+            }
+            Item::Use(i) => {
+                let (vis, colon, tree) = (&i.vis, &i.leading_colon, &i.tree);
+                quote!(#vis use #colon #tree)
+            }
+            item => quote!(#item),
+        },
+        ItemNode::ImplItem(item) => match item {
+            ImplItem::Const(i) => {
+                let (vis, ident, ty) = (&i.vis, &i.ident, &i.ty);
+                quote!(#vis const #ident: #ty)
+            }
+            ImplItem::Method(i) => {
+                let (vis, sig) = (&i.vis, &i.sig);
+                quote!(#vis #sig)
+            }
+            ImplItem::Type(i) => {
+                let (vis, ident, generics, ty) = (&i.vis, &i.ident, &i.generics, &i.ty);
+                quote!(#vis type #ident #generics = #ty)
+            }
+            ImplItem::Macro(i) => {
+                let path = &i.mac.path;
+                quote!(#path!)
+            }
+            item => quote!(#item),
+        },
+        ItemNode::TraitItem(item) => match item {
+            TraitItem::Const(i) => {
+                let (ident, ty) = (&i.ident, &i.ty);
+                quote!(const #ident: #ty)
+            }
+            TraitItem::Method(i) => {
+                let sig = &i.sig;
+                quote!(#sig)
+            }
+            TraitItem::Type(i) => {
+                let (ident, generics, colon, bounds) =
+                    (&i.ident, &i.generics, &i.colon_token, &i.bounds);
+                quote!(type #ident #generics #colon #bounds)
+            }
+            TraitItem::Macro(i) => {
+                let path = &i.mac.path;
+                quote!(#path!)
+            }
+            item => quote!(#item),
+        },
+        ItemNode::ForeignItem(item) => match item {
+            ForeignItem::Fn(i) => {
+                let (vis, sig) = (&i.vis, &i.sig);
+                quote!(#vis #sig)
+            }
+            ForeignItem::Static(i) => {
+                let (vis, mutability, ident, ty) = (&i.vis, &i.mutability, &i.ident, &i.ty);
+                quote!(#vis static #mutability #ident: #ty)
+            }
+            ForeignItem::Type(i) => {
+                let (vis, ident) = (&i.vis, &i.ident);
+                quote!(#vis type #ident)
+            }
+            ForeignItem::Macro(i) => {
+                let path = &i.mac.path;
+                quote!(#path!)
+            }
+            item => quote!(#item),
+        },
+    };
+    tokens_to_string(&tokens)
+}
+
+/// Where the part of an item that its signature leaves out starts, e.g. the block of a fn,
+/// the fields of a struct or the value of a const. `None` if the signature covers the whole item.
+pub fn body_start(node: ItemNode) -> Option<LineColumn> {
+    let span = match node {
+        ItemNode::Item(item) => match item {
+            Item::Const(i) => i.eq_token.span(),
+            Item::Enum(i) => i.brace_token.span,
+            Item::Fn(i) => i.block.brace_token.span,
+            Item::ForeignMod(i) => i.brace_token.span,
+            Item::Impl(i) => i.brace_token.span,
+            Item::Macro(i) => delimiter_span(&i.mac.delimiter),
+            Item::Macro2(i) => i.rules.span(),
+            Item::Mod(i) => i.content.as_ref()?.0.span,
+            Item::Static(i) => i.eq_token.span(),
+            Item::Struct(i) => match &i.fields {
+                Fields::Named(fields) => fields.brace_token.span,
+                Fields::Unnamed(fields) => fields.paren_token.span,
+                Fields::Unit => return None,
+            },
+            Item::Trait(i) => i.brace_token.span,
+            Item::Union(i) => i.fields.brace_token.span,
+            _ => return None,
+        },
+        ItemNode::ImplItem(item) => match item {
+            ImplItem::Const(i) => i.eq_token.span(),
+            ImplItem::Method(i) => i.block.brace_token.span,
+            ImplItem::Macro(i) => delimiter_span(&i.mac.delimiter),
+            _ => return None,
+        },
+        ItemNode::TraitItem(item) => match item {
+            TraitItem::Const(i) => i.default.as_ref()?.0.span(),
+            TraitItem::Method(i) => i.default.as_ref()?.brace_token.span,
+            TraitItem::Type(i) => i.default.as_ref()?.0.span(),
+            TraitItem::Macro(i) => delimiter_span(&i.mac.delimiter),
+            _ => return None,
+        },
+        ItemNode::ForeignItem(item) => match item {
+            ForeignItem::Macro(i) => delimiter_span(&i.mac.delimiter),
+            _ => return None,
+        },
+    };
+    Some(span.start())
+}
+
+fn delimiter_span(delimiter: &MacroDelimiter) -> proc_macro2::Span {
+    match delimiter {
+        MacroDelimiter::Paren(paren) => paren.span,
+        MacroDelimiter::Brace(brace) => brace.span,
+        MacroDelimiter::Bracket(bracket) => bracket.span,
+    }
+}
@@ -0,0 +1,249 @@
+use crate::matches::ItemMatch;
+use crate::util::ItemType;
+use crate::Compositor;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::Clap;
+
+/// Write a tags file for every item in the tree, for editors to jump to definitions.
+#[derive(Clap, Clone)]
+pub struct Tags {
+    /// Directory or file to index, instead of --path.
+    pub path: Option<PathBuf>,
+    /// Where to write the tags, `tags` by default or `TAGS` with --etags.
+    #[clap(short = 'o', long = "file")]
+    pub file: Option<PathBuf>,
+    /// Write an Emacs TAGS file instead of a ctags one.
+    #[clap(long)]
+    pub etags: bool,
+}
+
+impl Tags {
+    pub fn file(&self) -> PathBuf {
+        match &self.file {
+            Some(file) => file.clone(),
+            None if self.etags => "TAGS".into(),
+            None => "tags".into(),
+        }
+    }
+}
+
+/// A definition to write to the tags file.
+struct Tag {
+    name: String,
+    path: PathBuf,
+    line: usize,
+    /// Byte offset of the start of `line`.
+    offset: usize,
+    /// The source line up to the end of the name, which etags searches for.
+    pattern: String,
+    kind: char,
+    /// Enclosing impl or trait, e.g. `implementation:Client`.
+    scope: Option<String>,
+    signature: Option<String>,
+}
+
+impl Tag {
+    fn new(item: &ItemMatch) -> Option<Self> {
+        let kind = kind_letter(item.item_type)?;
+        let name = match (&item.ident, item.item_type) {
+            (Some(ident), _) => ident.clone(),
+            (None, ItemType::Impl) => implemented_type(item.scope.last()?),
+            (None, _) => return None,
+        };
+        let line = item.name_line?;
+
+        let mut lines = item.source.split_inclusive('\n');
+        let skipped: usize = lines
+            .by_ref()
+            .take(line - item.line_range.0)
+            .map(str::len)
+            .sum();
+        let text = lines.next().unwrap_or_default().trim_end();
+        let pattern = match text.find(&name) {
+            Some(i) => &text[..i + name.len()],
+            None => text,
+        };
+
+        // Only members of impls and traits are scoped, like ctags does.
+        let scope = match item.scope.len() {
+            n if n >= 2 => {
+                let parent = &item.scope[n - 2];
+                if parent.starts_with("impl ") {
+                    Some(format!("implementation:{}", implemented_type(parent)))
+                } else {
+                    parent
+                        .strip_prefix("trait ")
+                        .map(|name| format!("interface:{}", name))
+                }
+            }
+            _ => None,
+        };
+
+        Some(Tag {
+            name,
+            path: item.path.clone(),
+            line,
+            offset: item.source_start + skipped,
+            pattern: pattern.to_string(),
+            kind,
+            scope,
+            signature: item.signature.clone(),
+        })
+    }
+}
+
+/// The kind letters used by Universal Ctags for rust, `None` for items that aren't definitions.
+pub fn kind_letter(item_type: ItemType) -> Option<char> {
+    match item_type {
+        ItemType::Mod => Some('n'),
+        ItemType::Struct | ItemType::Union => Some('s'),
+        ItemType::Trait | ItemType::TraitAlias => Some('i'),
+        ItemType::Impl => Some('c'),
+        ItemType::Fn | ItemType::ForeignFn => Some('f'),
+        ItemType::Enum => Some('g'),
+        ItemType::Type | ItemType::ImplType | ItemType::TraitType | ItemType::ForeignType => {
+            Some('t')
+        }
+        ItemType::Static | ItemType::ForeignStatic => Some('v'),
+        ItemType::Macro | ItemType::Macro2 => Some('M'),
+        ItemType::ImplMethod | ItemType::TraitMethod => Some('P'),
+        ItemType::Const | ItemType::ImplConst | ItemType::TraitConst => Some('C'),
+        _ => None,
+    }
+}
+
+/// The name of the type an impl is for, from its label, e.g. `Client` for `impl<T> Display for Client<T>`.
+fn implemented_type(label: &str) -> String {
+    let ty = match label.rfind(" for ") {
+        Some(i) => &label[i + " for ".len()..],
+        None => label.trim_start_matches("impl "),
+    };
+    ty.split('<').next().unwrap_or(ty).trim().to_string()
+}
+
+/// Escape a field value the way Universal Ctags does.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t")
+}
+
+/// Collects the items it is given, and writes them as a ctags or etags file once finished.
+pub struct TagsPrinter {
+    file: PathBuf,
+    etags: bool,
+    tags: Vec<Tag>,
+}
+
+impl TagsPrinter {
+    pub fn new(tags: &Tags) -> Self {
+        Self {
+            file: tags.file(),
+            etags: tags.etags,
+            tags: Vec::new(),
+        }
+    }
+
+    fn write_ctags<W: Write>(&mut self, output: &mut W) -> std::io::Result<()> {
+        self.tags
+            .sort_by(|a, b| (&a.name, &a.path, a.line).cmp(&(&b.name, &b.path, b.line)));
+        writeln!(
+            output,
+            "!_TAG_FILE_FORMAT\t2\t/extended format; --format=1 will not append ;\" to lines/"
+        )?;
+        writeln!(
+            output,
+            "!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/"
+        )?;
+        writeln!(output, "!_TAG_PROGRAM_NAME\t{}\t//", env!("CARGO_PKG_NAME"))?;
+        writeln!(
+            output,
+            "!_TAG_PROGRAM_VERSION\t{}\t//",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        for tag in &self.tags {
+            write!(
+                output,
+                "{}\t{}\t{};\"\t{}\tline:{}",
+                tag.name,
+                tag.path.display(),
+                tag.line,
+                tag.kind,
+                tag.line
+            )?;
+            if let Some(scope) = &tag.scope {
+                write!(output, "\t{}", escape(scope))?;
+            }
+            if let Some(signature) = &tag.signature {
+                write!(output, "\tsignature:{}", escape(signature))?;
+            }
+            writeln!(output)?;
+        }
+        Ok(())
+    }
+
+    fn write_etags<W: Write>(&mut self, output: &mut W) -> std::io::Result<()> {
+        let mut files: BTreeMap<&PathBuf, Vec<&Tag>> = BTreeMap::new();
+        for tag in &self.tags {
+            files.entry(&tag.path).or_default().push(tag);
+        }
+        for (path, mut tags) in files {
+            tags.sort_by_key(|tag| tag.line);
+            let mut section = String::new();
+            for tag in tags {
+                section.push_str(&format!(
+                    "{}\x7f{}\x01{},{}\n",
+                    tag.pattern, tag.name, tag.line, tag.offset
+                ));
+            }
+            write!(
+                output,
+                "\x0c\n{},{}\n{}",
+                path.display(),
+                section.len(),
+                section
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for TagsPrinter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Compositor for TagsPrinter {
+    type Context = ItemMatch;
+
+    fn renders(&self) -> bool {
+        false
+    }
+
+    fn write_with(
+        &mut self,
+        _: std::fmt::Arguments,
+        item: Self::Context,
+    ) -> std::result::Result<(), std::io::Error> {
+        self.tags.extend(Tag::new(&item));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::result::Result<(), std::io::Error> {
+        let mut output = BufWriter::new(File::create(&self.file)?);
+        if self.etags {
+            self.write_etags(&mut output)?;
+        } else {
+            self.write_ctags(&mut output)?;
+        }
+        output.flush()
+    }
+}
@@ -0,0 +1,239 @@
+use crate::search::offset_of;
+
+use std::ops::Range;
+use std::str::FromStr;
+
+use color_eyre::{eyre, Report, Result};
+use proc_macro2::{Span, TokenStream, TokenTree};
+
+/// The kinds of source text a match can be restricted to with `--in`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    Ident,
+    /// Any comment, including doc comments.
+    Comment,
+    Doc,
+    String,
+    /// Anything that isn't a comment or a string literal.
+    Code,
+}
+
+impl FromStr for TokenClass {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ident" => Ok(TokenClass::Ident),
+            "comment" => Ok(TokenClass::Comment),
+            "doc" => Ok(TokenClass::Doc),
+            "string" => Ok(TokenClass::String),
+            "code" => Ok(TokenClass::Code),
+            _ => Err(eyre::eyre!(
+                "Invalid token class {:?}, expected one of: ident, comment, doc, string, code",
+                s
+            )),
+        }
+    }
+}
+
+/// The strict and reserved keywords, which `--in ident` leaves out.
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "macro",
+    "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+/// Byte ranges of a file's identifiers, comments and string literals, each sorted by start.
+#[derive(Debug, Default)]
+pub struct TokenMap {
+    idents: Vec<Range<usize>>,
+    comments: Vec<Range<usize>>,
+    docs: Vec<Range<usize>>,
+    strings: Vec<Range<usize>>,
+}
+
+impl TokenMap {
+    /// Lex the file with `proc_macro2`, using span locations to find the tokens in the source.
+    /// Returns `None` if the file can't be lexed.
+    pub fn new(contents: &str, byte_spans: &[usize]) -> Option<Self> {
+        let stream = TokenStream::from_str(contents).ok()?;
+        let mut map = TokenMap::default();
+        // Every byte range covered by a token, to find the comments in between.
+        let mut covered = Vec::new();
+        map.visit(stream, contents, byte_spans, &mut covered);
+        covered.sort_by_key(|r: &Range<usize>| r.start);
+
+        let mut end = 0;
+        for range in covered
+            .into_iter()
+            .chain(std::iter::once(contents.len()..contents.len()))
+        {
+            if range.start > end {
+                map.comments.extend(comments_in(contents, end..range.start));
+            }
+            end = end.max(range.end);
+        }
+        map.comments.extend(map.docs.iter().cloned());
+        map.comments.sort_by_key(|r| r.start);
+        Some(map)
+    }
+
+    fn visit(
+        &mut self,
+        stream: TokenStream,
+        contents: &str,
+        byte_spans: &[usize],
+        covered: &mut Vec<Range<usize>>,
+    ) {
+        let range_of = |span: Span| {
+            let (start, end) = (span.start(), span.end());
+            offset_of(contents, byte_spans, start.line, start.column)
+                ..offset_of(contents, byte_spans, end.line, end.column)
+        };
+        for tree in stream {
+            let range = range_of(tree.span());
+            // Doc comments are lexed as `#[doc = "..."]`, with every token spanning the comment.
+            let source = &contents[range.clone()];
+            if source.starts_with("//") || source.starts_with("/*") {
+                if self.docs.last() != Some(&range) {
+                    self.docs.push(range.clone());
+                    covered.push(range);
+                }
+                continue;
+            }
+            match tree {
+                TokenTree::Group(group) => {
+                    covered.push(range_of(group.span_open()));
+                    covered.push(range_of(group.span_close()));
+                    self.visit(group.stream(), contents, byte_spans, covered);
+                }
+                TokenTree::Ident(ident) => {
+                    // Keywords are lexed as identifiers too. Raw identifiers such as `r#match` keep their `r#`.
+                    if !KEYWORDS.contains(&ident.to_string().as_str()) {
+                        self.idents.push(range.clone());
+                    }
+                    covered.push(range);
+                }
+                TokenTree::Literal(literal) => {
+                    let literal = literal.to_string();
+                    if ["\"", "r\"", "r#", "b\"", "br"]
+                        .iter()
+                        .any(|prefix| literal.starts_with(prefix))
+                    {
+                        self.strings.push(range.clone());
+                    }
+                    covered.push(range);
+                }
+                TokenTree::Punct(_) => covered.push(range),
+            }
+        }
+    }
+
+    /// Whether the byte range falls within any of the given classes of text.
+    pub fn contains(&self, classes: &[TokenClass], range: Range<usize>) -> bool {
+        classes.iter().any(|class| match class {
+            TokenClass::Ident => within(&self.idents, &range),
+            TokenClass::Comment => within(&self.comments, &range),
+            TokenClass::Doc => within(&self.docs, &range),
+            TokenClass::String => within(&self.strings, &range),
+            TokenClass::Code => {
+                !overlaps(&self.comments, &range) && !overlaps(&self.strings, &range)
+            }
+        })
+    }
+}
+
+/// Whether `range` lies entirely within one of the sorted `ranges`.
+fn within(ranges: &[Range<usize>], range: &Range<usize>) -> bool {
+    let i = ranges.partition_point(|r| r.start <= range.start);
+    i > 0 && ranges[i - 1].end >= range.end
+}
+
+/// Whether `range` overlaps any of the sorted, non-overlapping `ranges`.
+fn overlaps(ranges: &[Range<usize>], range: &Range<usize>) -> bool {
+    let i = ranges.partition_point(|r| r.end <= range.start);
+    matches!(ranges.get(i), Some(r) if r.start < range.end.max(range.start + 1))
+}
+
+/// Find the comments in a stretch of source that holds no tokens.
+fn comments_in(contents: &str, gap: Range<usize>) -> Vec<Range<usize>> {
+    let mut comments = Vec::new();
+    let mut i = gap.start;
+    while i < gap.end {
+        let rest = &contents[i..gap.end];
+        if rest.starts_with("//") {
+            let len = rest.find('\n').unwrap_or(rest.len());
+            comments.push(i..i + len);
+            i += len;
+        } else if rest.starts_with("/*") {
+            let len = block_comment_len(rest);
+            comments.push(i..i + len);
+            i += len;
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    comments
+}
+
+/// Length of the (possibly nested) block comment at the start of `s`.
+fn block_comment_len(s: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < s.len() {
+        if s[i..].starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if s[i..].starts_with("*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += s[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    s.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The classes of each occurrence of `word` in the single line `source`.
+    fn classes_of(source: &str, word: &str) -> Vec<Vec<TokenClass>> {
+        let map = TokenMap::new(source, &[0, source.len()]).unwrap();
+        let all = [
+            TokenClass::Ident,
+            TokenClass::Comment,
+            TokenClass::Doc,
+            TokenClass::String,
+            TokenClass::Code,
+        ];
+        source
+            .match_indices(word)
+            .map(|(start, _)| {
+                all.iter()
+                    .copied()
+                    .filter(|class| map.contains(&[*class], start..start + word.len()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keywords_are_not_idents() {
+        use TokenClass::*;
+        assert_eq!(
+            classes_of("let r#let = 1; // let", "let"),
+            [vec![Code], vec![Ident, Code], vec![Comment]]
+        );
+        assert_eq!(
+            classes_of("impl Self { fn new(self) {} }", "self"),
+            [vec![Code]]
+        );
+        assert_eq!(classes_of("let union = 1;", "union"), [vec![Ident, Code]]);
+    }
+}
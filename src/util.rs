@@ -1,9 +1,17 @@
+use crate::matches::ItemMatch;
 
 use ignore::DirEntry;
+use quote::ToTokens;
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
-use syn::Item;
+use std::path::Path;
+use std::str::FromStr;
+use syn::{ForeignItem, Ident, ImplItem, Item, TraitItem};
 
-use color_eyre::Result;
+use color_eyre::{
+    eyre::{self, Context},
+    Report, Result,
+};
 
 use std::io::Write;
 
@@ -12,7 +20,8 @@ pub struct ParsedFile {
     pub dir_entry: DirEntry,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum ItemType {
     Fn,
     Enum,
@@ -31,6 +40,23 @@ pub enum ItemType {
     Union,
     Use,
     Verbatim,
+    ImplConst,
+    ImplMethod,
+    ImplType,
+    ImplMacro,
+    ImplVerbatim,
+    TraitConst,
+    TraitMethod,
+    TraitType,
+    TraitMacro,
+    TraitVerbatim,
+    ForeignFn,
+    ForeignStatic,
+    ForeignType,
+    ForeignMacro,
+    ForeignVerbatim,
+    /// A matching line in a file that could not be parsed.
+    Line,
 }
 
 pub fn item_type(item: &Item) -> ItemType {
@@ -56,26 +82,381 @@ pub fn item_type(item: &Item) -> ItemType {
     }
 }
 
+pub fn impl_item_type(item: &ImplItem) -> ItemType {
+    match item {
+        ImplItem::Const(_) => ItemType::ImplConst,
+        ImplItem::Method(_) => ItemType::ImplMethod,
+        ImplItem::Type(_) => ItemType::ImplType,
+        ImplItem::Macro(_) => ItemType::ImplMacro,
+        ImplItem::Verbatim(_) => ItemType::ImplVerbatim,
+        ImplItem::__TestExhaustive(_) => unreachable!(),
+    }
+}
+
+pub fn trait_item_type(item: &TraitItem) -> ItemType {
+    match item {
+        TraitItem::Const(_) => ItemType::TraitConst,
+        TraitItem::Method(_) => ItemType::TraitMethod,
+        TraitItem::Type(_) => ItemType::TraitType,
+        TraitItem::Macro(_) => ItemType::TraitMacro,
+        TraitItem::Verbatim(_) => ItemType::TraitVerbatim,
+        TraitItem::__TestExhaustive(_) => unreachable!(),
+    }
+}
+
+pub fn foreign_item_type(item: &ForeignItem) -> ItemType {
+    match item {
+        ForeignItem::Fn(_) => ItemType::ForeignFn,
+        ForeignItem::Static(_) => ItemType::ForeignStatic,
+        ForeignItem::Type(_) => ItemType::ForeignType,
+        ForeignItem::Macro(_) => ItemType::ForeignMacro,
+        ForeignItem::Verbatim(_) => ItemType::ForeignVerbatim,
+        ForeignItem::__TestExhaustive(_) => unreachable!(),
+    }
+}
+
+pub fn item_ident(item: &Item) -> Option<&Ident> {
+    match item {
+        Item::Const(i) => Some(&i.ident),
+        Item::Enum(i) => Some(&i.ident),
+        Item::ExternCrate(i) => Some(&i.ident),
+        Item::Fn(i) => Some(&i.sig.ident),
+        Item::Macro(i) => i.ident.as_ref(),
+        Item::Macro2(i) => Some(&i.ident),
+        Item::Mod(i) => Some(&i.ident),
+        Item::Static(i) => Some(&i.ident),
+        Item::Struct(i) => Some(&i.ident),
+        Item::Trait(i) => Some(&i.ident),
+        Item::TraitAlias(i) => Some(&i.ident),
+        Item::Type(i) => Some(&i.ident),
+        Item::Union(i) => Some(&i.ident),
+        _ => None,
+    }
+}
+
+pub fn impl_item_ident(item: &ImplItem) -> Option<&Ident> {
+    match item {
+        ImplItem::Const(i) => Some(&i.ident),
+        ImplItem::Method(i) => Some(&i.sig.ident),
+        ImplItem::Type(i) => Some(&i.ident),
+        _ => None,
+    }
+}
+
+pub fn trait_item_ident(item: &TraitItem) -> Option<&Ident> {
+    match item {
+        TraitItem::Const(i) => Some(&i.ident),
+        TraitItem::Method(i) => Some(&i.sig.ident),
+        TraitItem::Type(i) => Some(&i.ident),
+        _ => None,
+    }
+}
+
+pub fn foreign_item_ident(item: &ForeignItem) -> Option<&Ident> {
+    match item {
+        ForeignItem::Fn(i) => Some(&i.sig.ident),
+        ForeignItem::Static(i) => Some(&i.ident),
+        ForeignItem::Type(i) => Some(&i.ident),
+        _ => None,
+    }
+}
+
+impl ItemType {
+    pub const ALL: &'static [ItemType] = &[
+        ItemType::Fn,
+        ItemType::Enum,
+        ItemType::Const,
+        ItemType::ExternCrate,
+        ItemType::ForeignMod,
+        ItemType::Impl,
+        ItemType::Macro,
+        ItemType::Macro2,
+        ItemType::Mod,
+        ItemType::Static,
+        ItemType::Struct,
+        ItemType::Trait,
+        ItemType::TraitAlias,
+        ItemType::Type,
+        ItemType::Union,
+        ItemType::Use,
+        ItemType::Verbatim,
+        ItemType::ImplConst,
+        ItemType::ImplMethod,
+        ItemType::ImplType,
+        ItemType::ImplMacro,
+        ItemType::ImplVerbatim,
+        ItemType::TraitConst,
+        ItemType::TraitMethod,
+        ItemType::TraitType,
+        ItemType::TraitMacro,
+        ItemType::TraitVerbatim,
+        ItemType::ForeignFn,
+        ItemType::ForeignStatic,
+        ItemType::ForeignType,
+        ItemType::ForeignMacro,
+        ItemType::ForeignVerbatim,
+        ItemType::Line,
+    ];
+
+    /// The name used to refer to this item type on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ItemType::Fn => "fn",
+            ItemType::Enum => "enum",
+            ItemType::Const => "const",
+            ItemType::ExternCrate => "extern-crate",
+            ItemType::ForeignMod => "foreign-mod",
+            ItemType::Impl => "impl",
+            ItemType::Macro => "macro",
+            ItemType::Macro2 => "macro2",
+            ItemType::Mod => "mod",
+            ItemType::Static => "static",
+            ItemType::Struct => "struct",
+            ItemType::Trait => "trait",
+            ItemType::TraitAlias => "trait-alias",
+            ItemType::Type => "type",
+            ItemType::Union => "union",
+            ItemType::Use => "use",
+            ItemType::Verbatim => "verbatim",
+            ItemType::ImplConst => "impl-const",
+            ItemType::ImplMethod => "impl-method",
+            ItemType::ImplType => "impl-type",
+            ItemType::ImplMacro => "impl-macro",
+            ItemType::ImplVerbatim => "impl-verbatim",
+            ItemType::TraitConst => "trait-const",
+            ItemType::TraitMethod => "trait-method",
+            ItemType::TraitType => "trait-type",
+            ItemType::TraitMacro => "trait-macro",
+            ItemType::TraitVerbatim => "trait-verbatim",
+            ItemType::ForeignFn => "foreign-fn",
+            ItemType::ForeignStatic => "foreign-static",
+            ItemType::ForeignType => "foreign-type",
+            ItemType::ForeignMacro => "foreign-macro",
+            ItemType::ForeignVerbatim => "foreign-verbatim",
+            ItemType::Line => "line",
+        }
+    }
+
+    /// The keyword introducing this kind of item, used to label items in breadcrumbs.
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            ItemType::Fn | ItemType::ImplMethod | ItemType::TraitMethod | ItemType::ForeignFn => {
+                "fn"
+            }
+            ItemType::Const | ItemType::ImplConst | ItemType::TraitConst => "const",
+            ItemType::Type | ItemType::ImplType | ItemType::TraitType | ItemType::ForeignType => {
+                "type"
+            }
+            ItemType::Static | ItemType::ForeignStatic => "static",
+            ItemType::Macro
+            | ItemType::ImplMacro
+            | ItemType::TraitMacro
+            | ItemType::ForeignMacro => "macro_rules!",
+            ItemType::Enum => "enum",
+            ItemType::ExternCrate => "extern crate",
+            ItemType::ForeignMod => "extern",
+            ItemType::Impl => "impl",
+            ItemType::Macro2 => "macro",
+            ItemType::Mod => "mod",
+            ItemType::Struct => "struct",
+            ItemType::Trait | ItemType::TraitAlias => "trait",
+            ItemType::Union => "union",
+            ItemType::Use => "use",
+            ItemType::Verbatim
+            | ItemType::ImplVerbatim
+            | ItemType::TraitVerbatim
+            | ItemType::ForeignVerbatim
+            | ItemType::Line => "",
+        }
+    }
+}
+
+impl FromStr for ItemType {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        ItemType::ALL
+            .iter()
+            .find(|t| t.name() == s)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<&str> = ItemType::ALL.iter().map(ItemType::name).collect();
+                eyre::eyre!(
+                    "Invalid item kind {:?}, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
 impl Display for ItemType {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        write!(fmt, "{:?}", self)
+        // The same names --kind takes, so kinds can be copied from the output.
+        fmt.write_str(self.name())
     }
 }
 
-pub fn print_header_info<W: Write>(output: &mut W, file: &ParsedFile, item_type: ItemType) {
+pub fn print_header_info<W: Write>(output: &mut W, item: &ItemMatch) -> std::io::Result<()> {
+    let (start, end) = item.line_range;
     writeln!(
         output,
-        "[{}] {}",
-        item_type,
-        file.dir_entry.path().display()
-    )
-    .unwrap();
+        "[{}] {}:{}-{}",
+        item.item_type,
+        item.path.display(),
+        start,
+        end
+    )?;
+    writeln!(output, "{}", item.breadcrumb())
+}
+
+/// Derive the module path of a file from its location, e.g. `src/net/client.rs` is `crate::net::client`.
+/// Files outside of a `src` directory, and binaries under `src/bin`, are treated as crate roots.
+pub fn module_path(path: &Path) -> Vec<String> {
+    // The path may be relative to a directory inside `src`, so look at where the file really is,
+    // but only within its crate, as the directories above it may have a `src` of their own.
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let path = path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join("Cargo.toml").is_file())
+        .and_then(|root| path.strip_prefix(root).ok())
+        .unwrap_or(&path);
+    let mut components: Vec<String> = path
+        .with_extension("")
+        .iter()
+        .map(|c| c.to_string_lossy().into_owned())
+        .collect();
+    if matches!(
+        components.last().map(String::as_str),
+        Some("lib" | "main" | "mod")
+    ) {
+        components.pop();
+    }
+    let mut module = vec!["crate".to_string()];
+    if let Some(src) = components.iter().rposition(|c| c == "src") {
+        let rest = &components[src + 1..];
+        if rest.first().map(String::as_str) != Some("bin") {
+            module.extend(rest.iter().cloned());
+        }
+    }
+    module
+}
+
+/// Render tokens back to source, without the spaces `proc_macro2` puts between every token.
+pub fn tokens_to_string<T: ToTokens>(tokens: &T) -> String {
+    let mut string = tokens.to_token_stream().to_string();
+    for (from, to) in [
+        (" :: ", "::"),
+        (":: ", "::"),
+        (" : ", ": "),
+        (" < ", "<"),
+        ("< ", "<"),
+        (" <", "<"),
+        (" >", ">"),
+        (" ,", ","),
+        ("& ", "&"),
+        (" ;", ";"),
+        (" (", "("),
+        ("( ", "("),
+        (" )", ")"),
+        ("! ", "!"),
+        ("[ ", "["),
+        ("{ ", "{"),
+        (" }", "}"),
+        (" ]", "]"),
+        ("# [", "#["),
+    ] {
+        string = string.replace(from, to);
+    }
+    string
 }
 
 pub fn parse_file(dir_entry: DirEntry) -> Result<ParsedFile> {
-    let contents = std::fs::read_to_string(dir_entry.path())?;
+    let contents = std::fs::read_to_string(dir_entry.path())
+        .wrap_err_with(|| format!("Failed to read {}", dir_entry.path().display()))?;
     Ok(ParsedFile {
         contents,
         dir_entry,
     })
 }
+
+/// A directory of its own under the temp dir for a test to write files into.
+/// It is removed when dropped, so a failing test doesn't leave it behind.
+#[cfg(test)]
+pub struct TempDir(pub std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rgrok-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// Write a file into the directory, returning its path.
+    pub fn write(&self, name: &str, contents: &str) -> std::path::PathBuf {
+        let path = self.0.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Read a file of the directory back, as the walker would give it to a search.
+    pub fn parsed(&self, name: &str) -> ParsedFile {
+        let entry = ignore::Walk::new(self.0.join(name))
+            .next()
+            .unwrap()
+            .unwrap();
+        parse_file(entry).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn item_types_display_as_they_parse() {
+        for item_type in ItemType::ALL {
+            assert_eq!(
+                item_type.to_string().parse::<ItemType>().unwrap(),
+                *item_type
+            );
+        }
+        assert_eq!(ItemType::ImplMethod.to_string(), "impl-method");
+    }
+
+    #[test]
+    fn module_path_from_the_file_location() {
+        let dir = TempDir::new("module-path");
+        dir.write("Cargo.toml", "");
+        let client = dir.write("src/net/client.rs", "");
+        let main = dir.write("src/main.rs", "");
+        let bin = dir.write("src/bin/tool.rs", "");
+        let build = dir.write("build.rs", "");
+        assert_eq!(module_path(&client), ["crate", "net", "client"]);
+        assert_eq!(module_path(&main), ["crate"]);
+        assert_eq!(module_path(&bin), ["crate"]);
+        assert_eq!(module_path(&build), ["crate"]);
+        // Seen from within `src`.
+        let relative = dir.0.join("src/net/../net/client.rs");
+        assert_eq!(module_path(&relative), ["crate", "net", "client"]);
+    }
+
+    #[test]
+    fn module_path_ignores_src_above_the_crate() {
+        let dir = TempDir::new("module-path-above");
+        dir.write("src/proj/Cargo.toml", "");
+        let build = dir.write("src/proj/build.rs", "");
+        let lib = dir.write("src/proj/src/lib.rs", "");
+        assert_eq!(module_path(&build), ["crate"]);
+        assert_eq!(module_path(&lib), ["crate"]);
+    }
+}
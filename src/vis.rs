@@ -0,0 +1,102 @@
+use crate::attrs::has_attr;
+use crate::visit::{ancestors, ItemNode, ItemSpan, Node};
+
+use std::str::FromStr;
+
+use color_eyre::{eyre, Report, Result};
+use serde::{Deserialize, Serialize};
+use syn::{ForeignItem, ImplItem, Item, Visibility};
+
+/// How far an item is visible, from least to most visible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Vis {
+    Private,
+    /// `pub(crate)`, and anything else restricted to somewhere within the crate, like `pub(super)`.
+    Crate,
+    Pub,
+}
+
+impl FromStr for Vis {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pub" => Ok(Vis::Pub),
+            "pub(crate)" => Ok(Vis::Crate),
+            "private" => Ok(Vis::Private),
+            _ => Err(eyre::eyre!(
+                "Invalid visibility {:?}, expected one of: pub, pub(crate), private",
+                s
+            )),
+        }
+    }
+}
+
+impl From<&Visibility> for Vis {
+    fn from(vis: &Visibility) -> Self {
+        match vis {
+            Visibility::Public(_) => Vis::Pub,
+            Visibility::Inherited => Vis::Private,
+            Visibility::Restricted(restricted) if restricted.path.is_ident("self") => Vis::Private,
+            Visibility::Crate(_) | Visibility::Restricted(_) => Vis::Crate,
+        }
+    }
+}
+
+/// The visibility written on the item, or `None` if it takes the visibility of its parent,
+/// like impls, trait items and the items of trait impls.
+pub fn declared_vis(items: &[ItemSpan<ItemNode>], index: usize) -> Option<Vis> {
+    match items[index].node {
+        ItemNode::Item(item) => match item {
+            Item::Const(i) => Some((&i.vis).into()),
+            Item::Enum(i) => Some((&i.vis).into()),
+            Item::ExternCrate(i) => Some((&i.vis).into()),
+            Item::Fn(i) => Some((&i.vis).into()),
+            Item::Macro2(i) => Some((&i.vis).into()),
+            Item::Mod(i) => Some((&i.vis).into()),
+            Item::Static(i) => Some((&i.vis).into()),
+            Item::Struct(i) => Some((&i.vis).into()),
+            Item::Trait(i) => Some((&i.vis).into()),
+            Item::TraitAlias(i) => Some((&i.vis).into()),
+            Item::Type(i) => Some((&i.vis).into()),
+            Item::Union(i) => Some((&i.vis).into()),
+            Item::Use(i) => Some((&i.vis).into()),
+            Item::Macro(i) if i.ident.is_some() => Some(if has_attr(&i.attrs, "macro_export") {
+                Vis::Pub
+            } else {
+                Vis::Private
+            }),
+            _ => None,
+        },
+        ItemNode::ImplItem(item) => {
+            let trait_impl = items[index].parent.is_some_and(|parent| {
+                matches!(items[parent].node, ItemNode::Item(Item::Impl(i)) if i.trait_.is_some())
+            });
+            if trait_impl {
+                return None;
+            }
+            match item {
+                ImplItem::Const(i) => Some((&i.vis).into()),
+                ImplItem::Method(i) => Some((&i.vis).into()),
+                ImplItem::Type(i) => Some((&i.vis).into()),
+                _ => None,
+            }
+        }
+        ItemNode::TraitItem(_) => None,
+        ItemNode::ForeignItem(item) => match item {
+            ForeignItem::Fn(i) => Some((&i.vis).into()),
+            ForeignItem::Static(i) => Some((&i.vis).into()),
+            ForeignItem::Type(i) => Some((&i.vis).into()),
+            _ => None,
+        },
+    }
+}
+
+/// The visibility of an item, limited by the visibility of the items and inline modules it is in.
+/// The module a file declares can't be seen from the file itself, so file level items count as
+/// visible as they are declared.
+pub fn effective_vis<N: Node>(items: &[ItemSpan<N>], index: usize) -> Vis {
+    ancestors(items, index)
+        .map(|i| N::declared_vis(items, i).unwrap_or(Vis::Pub))
+        .min()
+        .unwrap_or(Vis::Pub)
+}
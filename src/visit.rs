@@ -0,0 +1,344 @@
+use crate::util::{
+    foreign_item_ident, foreign_item_type, impl_item_ident, impl_item_type, item_ident, item_type,
+    tokens_to_string, trait_item_ident, trait_item_type, ItemType,
+};
+
+use crate::attrs::{cfg_predicates, derives, has_attr};
+use crate::query::Shape;
+use crate::signature::{body_start, signature};
+use crate::vis::{declared_vis, Vis};
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use proc_macro2::Span;
+use serde::{Deserialize, Serialize};
+use syn::{spanned::Spanned, Attribute, ForeignItem, Ident, ImplItem, Item, Macro, TraitItem};
+
+/// The syntax tree of an item, at any level of nesting.
+#[derive(Clone, Copy)]
+pub enum ItemNode<'a> {
+    Item(&'a Item),
+    ImplItem(&'a ImplItem),
+    TraitItem(&'a TraitItem),
+    ForeignItem(&'a ForeignItem),
+}
+
+impl<'a> ItemNode<'a> {
+    pub fn attrs(&self) -> &'a [Attribute] {
+        match *self {
+            ItemNode::Item(item) => match item {
+                Item::Const(i) => &i.attrs,
+                Item::Enum(i) => &i.attrs,
+                Item::ExternCrate(i) => &i.attrs,
+                Item::Fn(i) => &i.attrs,
+                Item::ForeignMod(i) => &i.attrs,
+                Item::Impl(i) => &i.attrs,
+                Item::Macro(i) => &i.attrs,
+                Item::Macro2(i) => &i.attrs,
+                Item::Mod(i) => &i.attrs,
+                Item::Static(i) => &i.attrs,
+                Item::Struct(i) => &i.attrs,
+                Item::Trait(i) => &i.attrs,
+                Item::TraitAlias(i) => &i.attrs,
+                Item::Type(i) => &i.attrs,
+                Item::Union(i) => &i.attrs,
+                Item::Use(i) => &i.attrs,
+                _ => &[],
+            },
+            ItemNode::ImplItem(item) => match item {
+                ImplItem::Const(i) => &i.attrs,
+                ImplItem::Method(i) => &i.attrs,
+                ImplItem::Type(i) => &i.attrs,
+                ImplItem::Macro(i) => &i.attrs,
+                _ => &[],
+            },
+            ItemNode::TraitItem(item) => match item {
+                TraitItem::Const(i) => &i.attrs,
+                TraitItem::Method(i) => &i.attrs,
+                TraitItem::Type(i) => &i.attrs,
+                TraitItem::Macro(i) => &i.attrs,
+                _ => &[],
+            },
+            ItemNode::ForeignItem(item) => match item {
+                ForeignItem::Fn(i) => &i.attrs,
+                ForeignItem::Static(i) => &i.attrs,
+                ForeignItem::Type(i) => &i.attrs,
+                ForeignItem::Macro(i) => &i.attrs,
+                _ => &[],
+            },
+        }
+    }
+}
+
+/// What the filters and the output need from the syntax of an item.
+/// Freshly parsed items answer from their syntax tree, indexed ones from what the index recorded.
+pub trait Node: Sized {
+    /// Whether any of the item's attributes is named `name`, see [`has_attr`].
+    fn has_attr(&self, name: &str) -> bool;
+    /// Whether the item derives the trait `name`.
+    fn derives(&self, name: &str) -> bool;
+    fn cfg_predicates(&self) -> Vec<String>;
+    /// The visibility written on the item at `index`, or `None` if it takes its parent's.
+    fn declared_vis(items: &[ItemSpan<Self>], index: usize) -> Option<Vis>;
+    /// The types in the item's declaration, for queries.
+    fn shape(&self) -> Cow<'_, Shape>;
+    fn signature(&self) -> String;
+    /// 1-indexed line and 0-indexed char column where the part left out of the signature starts, see [`body_start`].
+    fn body_start(&self) -> Option<(usize, usize)>;
+}
+
+impl Node for ItemNode<'_> {
+    fn has_attr(&self, name: &str) -> bool {
+        has_attr(self.attrs(), name)
+    }
+
+    fn derives(&self, name: &str) -> bool {
+        derives(self.attrs(), name)
+    }
+
+    fn cfg_predicates(&self) -> Vec<String> {
+        cfg_predicates(self.attrs())
+    }
+
+    fn declared_vis(items: &[ItemSpan<Self>], index: usize) -> Option<Vis> {
+        declared_vis(items, index)
+    }
+
+    fn shape(&self) -> Cow<'_, Shape> {
+        Cow::Owned(Shape::of(self))
+    }
+
+    fn signature(&self) -> String {
+        signature(*self)
+    }
+
+    fn body_start(&self) -> Option<(usize, usize)> {
+        body_start(*self).map(|start| (start.line, start.column))
+    }
+}
+
+/// An item found in a file, along with where it lives in the item tree.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ItemSpan<N> {
+    pub node: N,
+    pub item_type: ItemType,
+    pub ident: Option<String>,
+    /// Short description of the item for breadcrumbs, e.g. `fn connect` or `impl Display for Client`.
+    pub label: String,
+    /// 1-indexed, inclusive line range of the item.
+    pub line_range: (usize, usize),
+    /// 0-indexed char columns of the start and end of the item, as reported by `proc_macro2`.
+    pub column_range: (usize, usize),
+    /// 1-indexed line of the item's name, or of the implementing type for impls, past any attributes.
+    pub name_line: Option<usize>,
+    /// Index of the enclosing item, if any.
+    pub parent: Option<usize>,
+}
+
+impl<N> ItemSpan<N> {
+    /// The same item, with another node standing for its syntax.
+    pub fn with_node<M>(&self, node: M) -> ItemSpan<M> {
+        ItemSpan {
+            node,
+            item_type: self.item_type,
+            ident: self.ident.clone(),
+            label: self.label.clone(),
+            line_range: self.line_range,
+            column_range: self.column_range,
+            name_line: self.name_line,
+            parent: self.parent,
+        }
+    }
+}
+
+/// Flatten the item tree of a file, descending into impls, traits, inline modules and foreign mods.
+/// Items are listed in pre-order, so a parent always comes before its children.
+pub fn collect_items(items: &[Item]) -> Vec<ItemSpan<ItemNode<'_>>> {
+    let mut out = Vec::new();
+    for item in items {
+        visit_item(item, None, &mut out);
+    }
+    out
+}
+
+fn push<'a>(
+    out: &mut Vec<ItemSpan<ItemNode<'a>>>,
+    node: ItemNode<'a>,
+    item_type: ItemType,
+    ident: Option<&Ident>,
+    label: Option<String>,
+    span: Span,
+    parent: Option<usize>,
+) -> usize {
+    let (start, end) = (span.start(), span.end());
+    let name_line = ident.map(|ident| ident.span().start().line);
+    let ident = ident.map(Ident::to_string);
+    let label = label.unwrap_or_else(|| match &ident {
+        Some(ident) => format!("{} {}", item_type.keyword(), ident),
+        None => item_type.keyword().to_string(),
+    });
+    out.push(ItemSpan {
+        node,
+        item_type,
+        ident,
+        label,
+        line_range: (start.line, end.line),
+        column_range: (start.column, end.column),
+        name_line,
+        parent,
+    });
+    out.len() - 1
+}
+
+fn visit_item<'a>(item: &'a Item, parent: Option<usize>, out: &mut Vec<ItemSpan<ItemNode<'a>>>) {
+    let index = push(
+        out,
+        ItemNode::Item(item),
+        item_type(item),
+        item_ident(item),
+        item_label(item),
+        item.span(),
+        parent,
+    );
+    match item {
+        Item::Impl(item_impl) => {
+            out[index].name_line = Some(item_impl.self_ty.span().start().line);
+            for item in &item_impl.items {
+                let label = match item {
+                    ImplItem::Macro(m) => Some(macro_label(&m.mac)),
+                    _ => None,
+                };
+                push(
+                    out,
+                    ItemNode::ImplItem(item),
+                    impl_item_type(item),
+                    impl_item_ident(item),
+                    label,
+                    item.span(),
+                    Some(index),
+                );
+            }
+        }
+        Item::Trait(item_trait) => {
+            for item in &item_trait.items {
+                let label = match item {
+                    TraitItem::Macro(m) => Some(macro_label(&m.mac)),
+                    _ => None,
+                };
+                push(
+                    out,
+                    ItemNode::TraitItem(item),
+                    trait_item_type(item),
+                    trait_item_ident(item),
+                    label,
+                    item.span(),
+                    Some(index),
+                );
+            }
+        }
+        Item::ForeignMod(foreign_mod) => {
+            for item in &foreign_mod.items {
+                let label = match item {
+                    ForeignItem::Macro(m) => Some(macro_label(&m.mac)),
+                    _ => None,
+                };
+                push(
+                    out,
+                    ItemNode::ForeignItem(item),
+                    foreign_item_type(item),
+                    foreign_item_ident(item),
+                    label,
+                    item.span(),
+                    Some(index),
+                );
+            }
+        }
+        Item::Mod(item_mod) => {
+            if let Some((_, items)) = &item_mod.content {
+                for item in items {
+                    visit_item(item, Some(index), out);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Labels for items that aren't described by their keyword and ident.
+fn item_label(item: &Item) -> Option<String> {
+    match item {
+        Item::Impl(item_impl) => {
+            let mut label = "impl ".to_string();
+            if let Some((bang, path, _)) = &item_impl.trait_ {
+                if bang.is_some() {
+                    label.push('!');
+                }
+                label.push_str(&tokens_to_string(path));
+                label.push_str(" for ");
+            }
+            label.push_str(&tokens_to_string(&item_impl.self_ty));
+            Some(label)
+        }
+        Item::ForeignMod(foreign_mod) => Some(tokens_to_string(&foreign_mod.abi)),
+        Item::Use(item_use) => Some(format!("use {}", tokens_to_string(&item_use.tree))),
+        Item::Macro(item_macro) if item_macro.ident.is_none() => Some(macro_label(&item_macro.mac)),
+        _ => None,
+    }
+}
+
+fn macro_label(mac: &Macro) -> String {
+    format!("{}!", tokens_to_string(&mac.path))
+}
+
+/// Find the innermost item whose byte range contains `offset`, given the byte range of each item.
+/// Several items can share a line, so lines alone can't tell them apart.
+pub fn item_at_offset(byte_ranges: &[Range<usize>], offset: usize) -> Option<usize> {
+    // Walking back from the last item starting at or before `offset` finds the innermost one first,
+    // since items are in pre-order.
+    let i = byte_ranges.partition_point(|range| range.start <= offset);
+    (0..i).rev().find(|&i| byte_ranges[i].contains(&offset))
+}
+
+/// Indexes of an item and its ancestors, innermost first.
+pub fn ancestors<N>(items: &[ItemSpan<N>], index: usize) -> impl Iterator<Item = usize> + '_ {
+    std::iter::successors(Some(index), move |&i| items[i].parent)
+}
+
+/// The labels of an item and its ancestors, outermost first.
+/// Inline modules are collected separately into the module path.
+pub fn item_scope<N>(items: &[ItemSpan<N>], index: usize) -> (Vec<String>, Vec<String>) {
+    let mut modules = Vec::new();
+    let mut scope = vec![items[index].label.clone()];
+    let mut parent = items[index].parent;
+    while let Some(i) = parent {
+        match (&items[i].item_type, &items[i].ident) {
+            (ItemType::Mod, Some(ident)) => modules.push(ident.clone()),
+            _ => scope.push(items[i].label.clone()),
+        }
+        parent = items[i].parent;
+    }
+    modules.reverse();
+    scope.reverse();
+    (modules, scope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_sharing_a_line() {
+        let file = syn::parse_file("impl Beta { fn one() {} fn two() {} }").unwrap();
+        let items = collect_items(&file.items);
+        // Everything is on line 1, so columns are byte offsets.
+        let ranges: Vec<Range<usize>> = items
+            .iter()
+            .map(|item| item.column_range.0..item.column_range.1)
+            .collect();
+        assert_eq!(item_at_offset(&ranges, 15), Some(1));
+        assert_eq!(item_at_offset(&ranges, 27), Some(2));
+        assert_eq!(item_at_offset(&ranges, 5), Some(0));
+        assert_eq!(item_at_offset(&ranges, 50), None);
+    }
+}